@@ -1,11 +1,13 @@
 use hajiman::{
     Bits, BitsIter, CharacterCounter, CharacterFrequency, JimiDecoder, JimiEncoder, JimiEncoding,
-    JimiError, LexemError, bits::Bits8, hajimi_tokens, serde_json,
+    JimiError, Key, LexemError, bits::Bits8, hajimi_tokens, serde_json,
 };
 use leptos::prelude::*;
 
 struct JimiState {
+    /// Encoding shown to the user, which is never keyed
     encoding: JimiEncoding<Bits8>,
+    key: Option<Key>,
     decoder: JimiDecoder<Bits8>,
     encoder: JimiEncoder<Bits8>,
 }
@@ -18,6 +20,7 @@ impl Default for JimiState {
 
         Self {
             encoding,
+            key: None,
             encoder,
             decoder,
         }
@@ -34,21 +37,36 @@ impl JimiState {
             return "".to_string();
         }
 
-        let freq = CharacterCounter::empty()
-            .count(Bits8::iter_bytes(input.as_bytes()).data)
-            .finish();
-        self.update(JimiEncoding::new(hajimi_tokens(), &freq))
+        let mut counter = CharacterCounter::empty();
+        counter.count(Bits8::iter_bytes(input.as_bytes()).data);
+        if let Some(key) = &self.key {
+            counter =
+                CharacterCounter::from_counts(&key.shuffle_counts::<Bits8>(&counter.counts()))
+                    .expect("shuffled counts are as many and as big");
+        }
+        self.update(JimiEncoding::new(hajimi_tokens(), &counter.finish()))
             .expect("hajimi_tokens can't produce non-prefix-free encoding");
         self.encode_with_current_encoding(input)
     }
 
     fn update(&mut self, encoding: JimiEncoding<Bits8>) -> Result<(), LexemError> {
+        let keyed = match &self.key {
+            Some(key) => encoding.keyed(key, false),
+            None => encoding.clone(),
+        };
+        self.decoder = keyed.decoder()?;
+        self.encoder = keyed.encoder();
         self.encoding = encoding;
-        self.encoder = self.encoding.encoder();
-        self.decoder = self.encoding.decoder()?;
         Ok(())
     }
 
+    /// Use the key derived from `passphrase`, or no key if it is empty
+    fn set_key(&mut self, passphrase: &str) {
+        self.key = (!passphrase.is_empty()).then(|| Key::new(passphrase));
+        self.update(self.encoding.clone())
+            .expect("keying does not change tokens");
+    }
+
     fn decode(&self, input: &str) -> Result<String, JimiError> {
         self.decoder.decode_to_vec(input).map_or_else(
             |e| Err(e.unwrap_parent()),
@@ -124,6 +142,7 @@ fn App() -> impl IntoView {
     let (last_modified, set_last_modified) = signal(LastModified::Plain);
     let (decode_error, set_decode_error) = signal("".to_string());
     let (encoding_error, set_encoding_error) = signal("".to_string());
+    let (passphrase, set_passphrase) = signal("".to_string());
 
     let update_plain = move |p: String| {
         if frequency_based.get() {
@@ -175,6 +194,15 @@ fn App() -> impl IntoView {
         }
     };
 
+    let update_passphrase = move |p: String| {
+        set_jimi.write().set_key(&p);
+        set_passphrase(p);
+        match last_modified.get() {
+            LastModified::Plain => update_plain(plain.get()),
+            LastModified::Encoded => set_plain(decode(encoded.read().as_str())),
+        }
+    };
+
     let encoding_json = move || {
         let _ = plain.read();
         let _ = encoded.read();
@@ -196,6 +224,14 @@ fn App() -> impl IntoView {
                         <label for="frequency-based">Frequency Based</label>
                     </div>
 
+                    <div class="key-container">
+                        <label for="key">密钥</label>
+                        <input id="key" type="password"
+                            prop:value=passphrase
+                            on:input:target=move |ev| update_passphrase(ev.target().value())
+                        />
+                    </div>
+
                     <div class="input-group">
                         <div class="text-container plaintext">
                             <label for="plaintext">
@@ -55,6 +55,17 @@ pub trait Bits: Seq + Eq + Ord + Debug + Clone {
     fn to_usize(self) -> usize;
     fn zero() -> Self;
     fn biggest() -> Self;
+
+    /// Number of symbols [`Bits::concat`] packs into whole bytes at a time
+    fn group_len() -> usize {
+        8 / bits::gcd(8, Self::N as usize)
+    }
+
+    /// Number of bytes a group of symbols packs into, and so the least that can be
+    /// encoded without padding
+    fn group_bytes() -> usize {
+        Self::group_len() * Self::N as usize / 8
+    }
 }
 
 pub struct BitsIter<B> {
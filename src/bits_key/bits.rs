@@ -1,6 +1,6 @@
 use super::*;
 
-fn gcd(mut a: usize, mut b: usize) -> usize {
+pub(super) fn gcd(mut a: usize, mut b: usize) -> usize {
     assert!(a != 0 && b != 0, "Inputs must be non-zero");
     while b != 0 {
         if b < a {
@@ -145,7 +145,8 @@ mod bits6 {
             it: impl Iterator<Item = Result<Self, E>>,
             mut writer: impl std::io::Write,
         ) -> Result<(), ConcatError<E>> {
-            for [x0, x1, x2, x3] in it.array_chunks::<4>() {
+            let mut chunks = it.array_chunks::<4>();
+            for [x0, x1, x2, x3] in chunks.by_ref() {
                 let [x0, x1, x2, x3] = [x0?, x1?, x2?, x3?];
                 let xs = [
                     (x0.0 << 2) | (x1.0 >> 4),
@@ -155,6 +156,8 @@ mod bits6 {
 
                 writer.write(&xs).map_err(ConcatError::Io)?;
             }
+            // Symbols short of a whole group are dropped, but not errors among them
+            chunks.into_remainder().try_for_each(|x| x.map(drop))?;
             Ok(())
         }
 
@@ -213,6 +216,13 @@ mod bits6 {
                 &[0b0100_1001, 0b1011_0110, 0b0011_0010, 0b1110_1010]
             );
         }
+
+        #[test]
+        fn test_concat_error_in_partial_group() {
+            let bits = [Ok(Bits6::from(1)), Ok(Bits6::from(2)), Err(())];
+            let result = Bits6::concat(bits.into_iter(), Vec::new());
+            assert!(matches!(result, Err(ConcatError::Parent(()))));
+        }
     }
 }
 pub use bits6::Bits6;
@@ -266,11 +276,14 @@ mod bits4 {
             it: impl Iterator<Item = Result<Self, E>>,
             mut writer: impl std::io::Write,
         ) -> Result<(), ConcatError<E>> {
-            for eles in it.array_chunks::<2>() {
+            let mut chunks = it.array_chunks::<2>();
+            for eles in chunks.by_ref() {
                 let [x0, x1] = eles;
                 let byte = x0?.0 << 4 | x1?.0;
                 writer.write(&[byte]).map_err(|e| ConcatError::Io(e))?;
             }
+            // Symbols short of a whole group are dropped, but not errors among them
+            chunks.into_remainder().try_for_each(|x| x.map(drop))?;
             Ok(())
         }
 
@@ -1,7 +1,7 @@
 use crate::bits_key::{Bits, BitsIter, BitsMap};
 
 pub struct CharacterFrequency<B> {
-    _freq: BitsMap<B, f32>,
+    freq: BitsMap<B, f32>,
     /// The accumulated frequency
     ///   $ P_k = p_0 + p_1 + dots + p_k $
     /// with $P_(-1)$ defined to zero.
@@ -16,6 +16,10 @@ impl<B> CharacterFrequency<B>
 where
     B: Bits,
 {
+    pub fn freq(&self, char: B) -> f32 {
+        self.freq[char]
+    }
+
     pub fn accu_freq(&self, char: B) -> f32 {
         self.accu_freq[char]
     }
@@ -29,6 +33,42 @@ where
     }
 }
 
+/// How symbols get a frequency when they are counted few times or none, which they
+/// need to be given a code at all
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Symbols never counted share up to 5% of the frequency, see
+    /// [`CharacterCounter::finish`]
+    Mend,
+    /// Every count is increased by this much, Laplace smoothing adding one
+    Additive(f32),
+}
+
+impl std::str::FromStr for Smoothing {
+    type Err = String;
+
+    /// Parse `mend`, `laplace` or `add:<alpha>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mend" => Ok(Smoothing::Mend),
+            "laplace" => Ok(Smoothing::Additive(1.0)),
+            _ => match s.strip_prefix("add:").map(str::parse::<f32>) {
+                Some(Ok(alpha)) if alpha.is_finite() && alpha > 0.0 => {
+                    Ok(Smoothing::Additive(alpha))
+                }
+                Some(_) => Err(format!(
+                    "expected a positive number after add:, found {:?}",
+                    s
+                )),
+                None => Err(format!(
+                    "expected mend, laplace or add:<alpha>, found {:?}",
+                    s
+                )),
+            },
+        }
+    }
+}
+
 pub struct CharacterCounter<B> {
     counts: BitsMap<B, usize>,
     total: usize,
@@ -52,6 +92,24 @@ where
         }
     }
 
+    /// Counter holding `counts` for each symbol in order, if there is one count per
+    /// symbol and not all of them are zero
+    pub fn from_counts(counts: &[usize]) -> Option<Self> {
+        if counts.len() != BitsMap::<B, usize>::len() || counts.iter().all(|&c| c == 0) {
+            return None;
+        }
+        let mut counter = Self::empty();
+        for (b, &count) in BitsIter::<B>::begin_zero().zip(counts) {
+            counter.counts[b] = count;
+            counter.total += count;
+        }
+        Some(counter)
+    }
+
+    pub fn counts(&self) -> Vec<usize> {
+        self.counts.iter().map(|(_, &count)| count).collect()
+    }
+
     pub fn count_one(&mut self, b: B) {
         self.counts[b] += 1;
         self.total += 1;
@@ -85,6 +143,23 @@ where
         characters_from_freq(freq)
     }
 
+    /// Frequency smoothed by `smoothing`, which may not be [`Smoothing::Mend`] for
+    /// counters of nothing
+    pub fn finish_with(&self, smoothing: Smoothing) -> CharacterFrequency<B> {
+        match smoothing {
+            Smoothing::Mend => self.finish(),
+            Smoothing::Additive(alpha) => {
+                let total = self.total as f32 + alpha * BitsMap::<B, usize>::len() as f32;
+                characters_from_freq(self.counts.map(|_, &n| (n as f32 + alpha) / total))
+            }
+        }
+    }
+
+    /// Total number of symbols counted
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
     fn freq(&self) -> BitsMap<B, f32> {
         let mut freq = BitsMap::new(0.0);
         for i in BitsIter::<B>::begin_zero() {
@@ -111,7 +186,7 @@ where
     }
 
     CharacterFrequency {
-        _freq: freq,
+        freq,
         accu_freq,
         accu_freq2,
     }
@@ -150,7 +225,7 @@ pub mod test {
         let freq = characters_from_freq(counter.freq());
 
         assert!(approx_iter(
-            freq._freq.iter().map(|(_, x)| *x),
+            freq.freq.iter().map(|(_, x)| *x),
             [0.1, 0.3, 0.4, 0.2].into_iter()
         ));
 
@@ -165,11 +240,29 @@ pub mod test {
         ));
     }
 
+    #[test]
+    fn test_smoothing() {
+        let mut counter = CharacterCounter::<Bits8>::empty();
+        counter.count([1, 1, 2].into_iter().map(Bits8::from));
+
+        let laplace = counter.finish_with("laplace".parse().unwrap());
+        assert!(approx(laplace.freq(Bits8::from(1)), 3.0 / 259.0));
+        assert!(approx(laplace.freq(Bits8::from(0)), 1.0 / 259.0));
+        assert!((laplace.accu_freq(Bits8::biggest()) - 1.0).abs() < 1e-4);
+
+        let empty = CharacterCounter::<Bits8>::empty().finish_with(Smoothing::Additive(0.5));
+        assert!(approx(empty.freq(Bits8::from(7)), 1.0 / 256.0));
+
+        assert_eq!("add:0.5".parse(), Ok(Smoothing::Additive(0.5)));
+        assert!("add:0".parse::<Smoothing>().is_err());
+        assert!("none".parse::<Smoothing>().is_err());
+    }
+
     #[test]
     fn test_all_equal_frequency() {
         let chars = CharacterFrequency::<Bits8>::all_equal();
 
-        for (_, freq) in chars._freq.iter() {
+        for (_, freq) in chars.freq.iter() {
             assert!(approx(*freq, 1.0 / (2usize.pow(Bits8::N)) as f32))
         }
     }
@@ -1,22 +1,56 @@
 use std::{
-    io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, Write, stdin, stdout},
+    io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write, stdin, stdout},
     path::PathBuf,
 };
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, builder::PossibleValuesParser};
 
+use crate::dyn_jimi::with_width;
 use crate::{
-    CharacterCounter, CharacterFrequency, JimiDecoder, JimiEncoder, JimiEncoding, JimiError,
-    bits::Bits8,
-    bits_key::{Bits, ConcatError},
-    hajimi_tokens, lexing,
+    CharacterCounter, CharacterFrequency, Checksum, Codebook, Compression, DynJimiEncoding,
+    Encryption, Fec, FecStats, Flag, Guess, Header, JimiDecoder, JimiEncoder, JimiEncoding, Key,
+    RawHeader, Redundancy, TokenSet, UnsupportedWidth, WIDTHS,
+    bits_key::Bits,
+    letters::LetterIdIndexed,
+    lexing::normalize::{NormalForm, Normalization},
+    transcode::Truncated,
 };
 
+mod batch;
+mod error;
+mod inspect;
+mod repl;
+mod tokens;
+mod train;
+mod transcode;
+mod verify;
+use batch::BatchArgs;
+pub use error::CliError;
+use error::io;
+use inspect::InspectArgs;
+use tokens::TokensCommand;
+use train::TrainArgs;
+use transcode::TranscodeArgs;
+use verify::VerifyArgs;
+
+/// Environment variable the passphrase is read from when `--passphrase` is not given
+const PASSPHRASE_ENV: &str = "HAJIMAN_PASSPHRASE";
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success
+  2  bad usage of arguments or files
+  3  reading input or writing output failed
+  4  header of the input or of the encoding file is malformed
+  5  honey water cannot be decoded
+  6  checksum mismatch or failed decryption, the data is corrupted";
+
 #[derive(Parser)]
 #[command(
     version,
     author,
-    about = "Command line utility to cipher and decipher honey water codec"
+    about = "Command line utility to cipher and decipher honey water codec",
+    after_help = EXIT_CODES
 )]
 pub struct Cli {
     #[command(subcommand)]
@@ -26,7 +60,7 @@ pub struct Cli {
     /// Input from file instead of standard input or command line argument
     input_file: Option<String>,
 
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     /// Output to file instead of standard output
     output_file: Option<String>,
 
@@ -34,15 +68,163 @@ pub struct Cli {
     /// If set to true, encoding will be created based on frequency of bytes;
     frequency_based: bool,
 
-    #[arg(short, long)]
-    /// Read encoding from file.
+    #[arg(short, long, global = true)]
+    /// Read encoding from the header of a file, such as previously encoded output.
     ///
+    /// Bare JSON encodings written by earlier versions are accepted too.
     /// This argument has higher precedence then `--frequency-based`
     encoding_file: Option<PathBuf>,
 
-    #[arg(short, long, default_value = "false")]
+    #[arg(short, long, global = true, conflicts_with = "encoding_file")]
+    /// Build the encoding from tokens listed in this file instead of the hajimi ones.
+    ///
+    /// Either one letter per line, with aliases following the token separated by spaces,
+    /// a JSON list whose elements are tokens or lists of aliases,
+    /// or a TOML file with such a list under the `tokens` key.
+    tokens: Option<PathBuf>,
+
+    #[arg(
+        long,
+        global = true,
+        conflicts_with_all = ["encoding_file", "tokens"],
+        value_parser = PossibleValuesParser::new(TokenSet::all().iter().map(TokenSet::name)),
+    )]
+    /// Build the encoding from a built-in token set, see `tokens list`.
+    ///
+    /// Defaults to `hajimi`. The encoding refers to the set by name instead of listing tokens.
+    token_set: Option<String>,
+
+    #[arg(long, default_value = "false")]
+    /// Append a CRC32 checksum of the input to the honey water when encoding,
+    /// so that decoding detects lost or altered tokens
+    checksum: bool,
+
+    #[arg(long, value_name = "DATA+PARITY", conflicts_with = "checksum")]
+    /// Add Reed–Solomon parity when encoding, such as `16+4` for 4 parity shards
+    /// every 16 shards of data.
+    ///
+    /// Each shard is written on its own line with its index and a CRC32, and shards
+    /// that fail to decode are rebuilt from the others, up to the number of parity
+    /// shards per block.
+    fec: Option<Redundancy>,
+
+    #[arg(long, global = true, value_name = "PASSPHRASE")]
+    /// Shuffle which byte gets which code with a key derived from this passphrase.
+    ///
+    /// The key is never written out, and the same passphrase is needed to decode.
+    key: Option<String>,
+
+    #[arg(long, default_value = "false", requires = "key")]
+    /// With `--key`, shuffle tokens of the same length too
+    key_tokens: bool,
+
+    #[arg(long, value_enum, value_name = "METHOD")]
+    /// Compress the input before encoding it, and encrypting it if asked to.
+    ///
+    /// `auto` picks whichever method makes the honey water shortest, or none if no
+    /// method makes it shorter.
+    compress: Option<CompressArg>,
+
+    #[arg(long, default_value = "false")]
+    /// Print how long the honey water comes out with and without each compression
+    /// method to standard error when encoding
+    stats: bool,
+
+    #[arg(long, default_value = "false")]
+    /// Encrypt the input with ChaCha20-Poly1305 before encoding it, with a key derived
+    /// from the passphrase by Argon2id.
+    ///
+    /// The salt and nonce are recorded in the header, and decoding needs the same
+    /// passphrase.
+    encrypt: bool,
+
+    #[arg(long, value_name = "PASSPHRASE")]
+    /// Passphrase to encrypt with, or to decrypt encrypted input with.
+    ///
+    /// Read from the `HAJIMAN_PASSPHRASE` environment variable if not given.
+    passphrase: Option<String>,
+
+    #[arg(long, default_value = "false")]
+    /// Decode input without a header with the default encoding, instead of guessing
+    /// which token set and width it is encoded with.
+    ///
+    /// There is no guessing when `--encoding-file`, `--tokens`, `--token-set` or
+    /// `--bits` is given either.
+    no_detect: bool,
+
+    #[arg(long, default_value = "false")]
+    /// Fail decoding unless the input carries a checksum.
+    ///
+    /// Input without a header is then assumed to end with a checksum.
+    require_checksum: bool,
+
+    #[arg(long, global = true, value_parser = parse_width)]
+    /// Width of the symbols bytes are split into, each getting a code: 4, 6 or 8.
+    ///
+    /// Defaults to 8, or the width of `--encoding-file`. When decoding, the header of the
+    /// input decides, and this only applies to input without one.
+    bits: Option<u32>,
+
+    #[arg(short, long, global = true, default_value = "false")]
     /// Whether to output encoding in pretty JSON
     pretty_encoding: bool,
+
+    #[arg(long, global = true, value_enum)]
+    /// Unicode normal form tokens and input are brought to before matching when decoding.
+    ///
+    /// Recorded in the encoding, overriding what a loaded encoding says.
+    normal_form: Option<NormalFormArg>,
+
+    #[arg(long, global = true, default_value = "false")]
+    /// Match tokens case-insensitively when decoding.
+    ///
+    /// Recorded in the encoding, overriding what a loaded encoding says.
+    case_fold: bool,
+
+    #[arg(
+        short,
+        long,
+        global = true,
+        default_value = "false",
+        conflicts_with = "verbose"
+    )]
+    /// Print nothing but errors to standard error
+    quiet: bool,
+
+    #[arg(short, long, global = true, default_value = "false")]
+    /// Also print the header and sizes of what is encoded or decoded to standard error
+    verbose: bool,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum CompressArg {
+    Auto,
+    Deflate,
+    Zstd,
+}
+
+/// Parse `--bits`, which has to be one of [`WIDTHS`]
+fn parse_width(s: &str) -> Result<u32, String> {
+    let bits = s.parse().map_err(|e| format!("{}", e))?;
+    match WIDTHS.contains(&bits) {
+        true => Ok(bits),
+        false => Err(UnsupportedWidth(bits).to_string()),
+    }
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum NormalFormArg {
+    Nfc,
+    Nfkc,
+}
+
+impl From<NormalFormArg> for NormalForm {
+    fn from(value: NormalFormArg) -> Self {
+        match value {
+            NormalFormArg::Nfc => NormalForm::Nfc,
+            NormalFormArg::Nfkc => NormalForm::Nfkc,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -52,20 +234,68 @@ pub enum Command {
     /// If `--frequency-based` is set to false and no `--encoding-file` is provided,
     /// encoding is created assuming all bytes appear with uniform probability.
     ///
-    /// Before outputing encoded data, the encoding it self is outputed with JSON format.
+    /// Before outputing encoded data, a header is outputed, recording the encoding,
+    /// symbol width and length of the input, unless it is streamed from standard input.
     Encode {
         /// Input from command line argument intead of standard input
         data: Option<String>,
+
+        #[command(flatten)]
+        batch: BatchArgs,
+
+        #[arg(long, default_value = "false", requires = "recursive")]
+        /// With `--recursive` and `--frequency-based`, build one encoding from all files
+        /// instead of one for each file
+        shared_encoding: bool,
     },
     /// Decode honey water.
     ///
-    /// If `--encoding-file` is not provided,
-    /// we will first look for enclosed JSON format of encoding from begining of the input;
+    /// The header at the begining of the input is read first, and its encoding used
+    /// unless `--encoding-file` is provided. A malformed header is an error.
     ///
-    /// If that is not found, we fall back to uniform probability encoding.
+    /// If the input has no header, we guess which uniform probability encoding of a
+    /// built-in token set it is encoded with, and tell which.
     Decode {
         /// Input from command line argument intead of standard input
         data: Option<String>,
+
+        #[command(flatten)]
+        batch: BatchArgs,
+    },
+    /// Build an encoding from the frequency of symbols in files, to use with
+    /// `--encoding-file`.
+    ///
+    /// The encoding is written as a header, and statistics on the files to standard
+    /// error. With `--key`, the encoding is built to be as compact once keyed with it.
+    Train(TrainArgs),
+    /// Show which code and tokens each symbol gets in an encoding.
+    ///
+    /// Probabilities are those counted when the encoding was built, if it records them,
+    /// or else those its codes are best for.
+    Inspect(InspectArgs),
+    /// Encode a file and decode it back with every token set and symbol width,
+    /// reporting expansion, timings and whether padding is dropped.
+    ///
+    /// Every option of `encode` applies, such as compression, encryption and FEC.
+    /// `--tokens`, `--token-set` and `--bits` narrow down what is tried.
+    Verify(VerifyArgs),
+    /// Decode honey water and encode it again with another encoding, of any symbol
+    /// width, without holding the payload whole.
+    ///
+    /// The input is decoded like with `decode`, with `--from` taking the place of
+    /// `--encoding-file`. The length, compression and encryption recorded in its header
+    /// carry over, and the key applies to both encodings. Without a length, zero bytes
+    /// padding the last group of symbols of the input are kept.
+    Transcode(TranscodeArgs),
+    /// Encode or decode lines as they are typed, with commands to change the encoding.
+    ///
+    /// Lines which lex as honey water are decoded, and others encoded. `:help` lists
+    /// commands.
+    Repl,
+    /// Work with token sets.
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
     },
 }
 use Command::*;
@@ -73,267 +303,890 @@ use Command::*;
 impl Cli {
     fn data(&self) -> &Option<String> {
         match &self.command {
-            Encode { data } => data,
-            Decode { data } => data,
+            Encode { data, .. } => data,
+            Decode { data, .. } => data,
+            Transcode(args) => args.data(),
+            Train(_) | Inspect(_) | Verify(_) | Repl | Tokens { .. } => &None,
         }
     }
-}
 
-type Enc = JimiEncoding<Bits8>;
+    /// Batch arguments, if asked to process a directory
+    fn batch(&self) -> Option<&BatchArgs> {
+        match &self.command {
+            Encode { batch, .. } | Decode { batch, .. } => {
+                batch.recursive.is_some().then_some(batch)
+            }
+            Train(_) | Inspect(_) | Verify(_) | Transcode(_) | Repl | Tokens { .. } => None,
+        }
+    }
+
+    fn passphrase(&self) -> Result<String, CliError> {
+        self.passphrase
+            .clone()
+            .or_else(|| std::env::var(PASSPHRASE_ENV).ok())
+            .ok_or_else(|| {
+                CliError::Usage(format!(
+                    "a passphrase is needed, pass it with --passphrase or {}",
+                    PASSPHRASE_ENV
+                ))
+            })
+    }
+
+    /// Tell what happened on standard error, unless `--quiet`
+    fn note(&self, message: impl std::fmt::Display) {
+        if !self.quiet {
+            eprintln!("{message}");
+        }
+    }
 
-fn read_encoding<'a>(
-    mut input: impl Read,
-) -> Option<Result<JimiEncoding<Bits8>, serde_json::Error>> {
-    let mut deserializer = serde_json::Deserializer::from_reader(&mut input).into_iter::<Enc>();
-    deserializer.next()
+    /// Tell details on standard error, only with `--verbose`
+    fn detail(&self, message: impl std::fmt::Display) {
+        if self.verbose {
+            eprintln!("{message}");
+        }
+    }
+
+    fn normalization(&self) -> Normalization {
+        Normalization {
+            form: self.normal_form.map(NormalForm::from),
+            case_fold: self.case_fold,
+        }
+    }
 }
 
-fn count_character(
-    reader: &mut dyn ReadSeek,
-    counter: &mut CharacterCounter<Bits8>,
-) -> std::io::Result<()> {
-    let mut buf = vec![0; 512];
+fn read_encoding_file(path: &PathBuf) -> Result<RawHeader, CliError> {
+    let f = std::fs::File::open(path).map_err(io(format!("open file {:?}", path)))?;
+    match RawHeader::read(BufReader::new(f)) {
+        Ok((Some(header), _)) => Ok(header),
+        Ok((None, _)) => Err(CliError::MalformedHeader(format!(
+            "encoding file {:?} does not contain an encoding",
+            path
+        ))),
+        Err(e) => Err(CliError::from(e).context(format!("encoding file {:?}", path))),
+    }
+}
+
+/// Count the symbols of everything from `reader`, returning how many bytes were read
+fn count_character<B: Bits>(
+    reader: &mut dyn BufRead,
+    counter: &mut CharacterCounter<B>,
+) -> std::io::Result<u64> {
+    // Whole groups of symbols, so that only the end can be padded
+    let mut buf = vec![0; 64 * B::N as usize * B::group_len()];
+    let mut total = 0;
     loop {
-        match reader.read(&mut buf) {
-            Ok(0) => return Ok(()),
-            Ok(n) => {
-                counter.count(Bits8::iter_bytes(&buf[..n]).data);
+        match read_full(reader, &mut buf)? {
+            0 => return Ok(total),
+            n => {
+                counter.count(B::iter_bytes(&buf[..n]).data);
+                total += n as u64;
             }
-            Err(e) => return Err(e),
         }
     }
 }
 
-fn encode(
-    reader: &mut dyn ReadSeek,
-    encoder: &JimiEncoder<Bits8>,
+/// Encode everything from `reader`, followed by a checksum trailer if `checksum` is set
+fn encode<B: Bits>(
+    reader: &mut dyn BufRead,
+    encoder: &JimiEncoder<B>,
     mut writer: impl Write,
-) -> Result<(), String> {
-    let mut buf = vec![0; 512];
+    checksum: bool,
+) -> Result<(), CliError> {
+    // Whole groups of symbols, so that only the end can be padded
+    let mut buf = vec![0; 512 * B::group_bytes()];
+    let mut hasher = Checksum::<B>::new();
     loop {
-        match reader.read(&mut buf) {
-            Ok(0) => return Ok(()),
-            Ok(n) => {
+        match read_full(reader, &mut buf).map_err(io("read input"))? {
+            0 if checksum => {
+                for s in encoder.encode_checksum(hasher.finish()) {
+                    writer.write_all(s.as_bytes()).map_err(io("write output"))?;
+                }
+                return Ok(());
+            }
+            0 => return Ok(()),
+            n => {
+                hasher.update(&buf[..n]);
                 let encoded = encoder.encode(&buf[..n]);
                 for s in encoded.data {
-                    writer
-                        .write(s.as_bytes())
-                        .map_err(|e| format!("write output failed: {}", e))?;
+                    writer.write_all(s.as_bytes()).map_err(io("write output"))?;
                 }
             }
-            Err(e) => return Err(format!("read input failed: {}", e)),
         }
     }
 }
 
-fn encode_enclosing_encoding(
-    reader: &mut dyn ReadSeek,
-    encoding: &JimiEncoding<Bits8>,
-    encoder: &JimiEncoder<Bits8>,
+/// Encode everything from `reader` with Reed–Solomon parity, one shard per line
+fn encode_fec<B: Bits>(
+    reader: &mut dyn BufRead,
+    encoder: &JimiEncoder<B>,
+    fec: &Fec,
+    mut writer: impl Write,
+) -> Result<(), CliError> {
+    let mut buf = vec![0; fec.block_len()];
+    for block in 0.. {
+        let n = read_full(reader, &mut buf).map_err(io("read input"))?;
+        if n == 0 {
+            break;
+        }
+        for frame in fec.encode_block(block, &buf[..n]) {
+            for s in encoder.encode(&frame).data {
+                writer.write_all(s.as_bytes()).map_err(io("write output"))?;
+            }
+            writeln!(writer).map_err(io("write output"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Read until `buf` is full or the input ends, returning how many bytes were read
+fn read_full(reader: &mut dyn BufRead, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+fn encode_with_header<B>(
+    reader: &mut dyn BufRead,
+    header: &Header<B>,
+    key: Option<&Key>,
     mut writer: impl Write,
     pretty_encoding: bool,
-) -> Result<(), String> {
-    if pretty_encoding {
-        serde_json::to_writer_pretty(&mut writer, &encoding)
-            .map_err(|e| format!("write encoding to output failed: {}", e))?;
+) -> Result<(), CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let encoder = header
+        .keyed_encoding(key)
+        .map_err(CliError::Usage)?
+        .encoder();
+    header
+        .write(&mut writer, pretty_encoding)
+        .map_err(io("write header to output"))?;
+    match header.fec {
+        Some(redundancy) => {
+            let fec = Fec::new(redundancy)?;
+            encode_fec(reader, &encoder, &fec, writer)
+        }
+        None => encode(
+            reader,
+            &encoder,
+            writer,
+            header.flags.contains(&Flag::Crc32),
+        ),
+    }
+}
+
+/// Decode honey water from `reader`, expecting `length` bytes if known, and a
+/// checksum trailer if `checked` is set
+fn decode<B: Bits>(
+    reader: &mut dyn BufRead,
+    decoder: &JimiDecoder<B>,
+    writer: impl Write,
+    length: Option<u64>,
+    checked: bool,
+) -> Result<(), CliError> {
+    let mut writer = Truncated::new(BufWriter::new(writer), length);
+    if checked {
+        decoder.decode_reader_checked(reader, &mut writer)
     } else {
-        serde_json::to_writer(&mut writer, &encoding)
-            .map_err(|e| format!("write encoding to output failed: {}", e))?;
+        decoder.decode_reader(reader, &mut writer)
+    }?;
+    writer.flush().map_err(io("write output"))?;
+
+    match length {
+        Some(length) if writer.remaining > 0 => Err(CliError::Decode(format!(
+            "honey water ends early: decoded {} of {} bytes",
+            writer.written, length
+        ))),
+        _ => Ok(()),
     }
-    write!(&mut writer, "\n").map_err(|e| format!("write newline to output failed: {}", e))?;
-    encode(reader, &encoder, writer)?;
+}
 
-    Ok(())
+/// Decode honey water written by [`encode_fec`], rebuilding the lines that fail to
+/// decode from the others
+fn decode_fec<B: Bits>(
+    reader: &mut dyn BufRead,
+    decoder: &JimiDecoder<B>,
+    fec: &Fec,
+    writer: impl Write,
+    length: Option<u64>,
+) -> Result<FecStats, CliError> {
+    let mut writer = Truncated::new(BufWriter::new(writer), length);
+    let mut fec_decoder = fec.decoder(&mut writer);
+    for line in decoder.decode_lines_lenient(reader) {
+        match line.map_err(io("read input"))? {
+            Ok(frame) => fec_decoder.push(&frame),
+            Err(_) => Ok(()),
+        }?;
+    }
+    Ok(fec_decoder.finish(length)?)
 }
 
-fn skip_until_newline(reader: &mut dyn ReadSeek) -> Result<(), String> {
-    let _ = reader
-        .skip_until(b'\n')
-        .map_err(|e| format!("skip until newline failed: {e}"))?;
-    Ok(())
+/// Print the size of `payload` and its honey water, as measured by `honey_water_len`,
+/// with each compression method
+fn print_stats(
+    payload: &[u8],
+    chosen: Option<Compression>,
+    honey_water_len: impl Fn(&[u8]) -> usize,
+) {
+    eprintln!("{:<10} {:>12} {:>12}", "method", "payload", "honey water");
+    let methods = std::iter::once(None).chain(Compression::ALL.map(Some));
+    for compression in methods {
+        let compressed = match compression {
+            Some(compression) => compression.compress(payload),
+            None => payload.to_vec(),
+        };
+        eprintln!(
+            "{:<10} {:>12} {:>12}{}",
+            compression.map_or("none", Compression::name),
+            compressed.len(),
+            honey_water_len(&compressed),
+            if compression == chosen { "  *" } else { "" }
+        );
+    }
 }
 
-fn decode(
-    reader: &mut dyn ReadSeek,
-    decoder: &JimiDecoder<Bits8>,
-    mut writer: impl Write,
-) -> Result<(), String> {
-    let mut iter = decoder.decode_chars("".to_string().into_chars());
+/// One line telling what a header records, for `--verbose`
+fn describe_header<B>(header: &Header<B>) -> String
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut s = format!(
+        "header: {}-bit symbols, {} tokens, {} bytes",
+        B::N,
+        header.encoding.tokens().len(),
+        header
+            .length
+            .map_or_else(|| "unknown".to_string(), |n| n.to_string()),
+    );
+    if !header.flags.is_empty() {
+        s += &format!(", flags {:?}", header.flags);
+    }
+    if let Some(redundancy) = header.fec {
+        s += &format!(
+            ", fec {}+{}",
+            redundancy.data_shards, redundancy.parity_shards
+        );
+    }
+    if let Some(compression) = header.compression {
+        s += &format!(", {}", compression.name());
+    }
+    if header.encryption.is_some() {
+        s += ", encrypted";
+    }
+    s
+}
 
-    let mut offset = 0;
-    let mut buf = vec![0; 512];
-    let mut termination_error = None;
+trait ReadSeek: BufRead + Seek {}
 
-    loop {
-        match reader.read(&mut buf[offset..]) {
-            Ok(0) => {
-                if offset != 0 {
-                    return Err(format!("input is not complete UTF-8 string"));
-                } else if let Some(te) = termination_error {
-                    return Err(format!("error parsing honey water: {:?}", te));
-                } else {
-                    return Ok(());
-                }
+impl<T> ReadSeek for BufReader<T> where T: Seek + Read {}
+impl<T> ReadSeek for Cursor<T> where T: AsRef<[u8]> {}
+
+/// Bytes of standard input kept in memory when it has to be read twice, beyond which
+/// it is spooled to a temporary file
+const SPOOL_MEMORY: usize = 8 << 20;
+
+/// Where the payload is read from.
+///
+/// Standard input is streamed through as raw bytes, and only spooled when it has to be
+/// read twice or its length must be known before encoding.
+enum Input {
+    Seekable(Box<dyn ReadSeek>),
+    Stream(Box<dyn BufRead>),
+}
+
+impl Input {
+    fn reader(&mut self) -> &mut dyn BufRead {
+        match self {
+            Input::Seekable(reader) => reader.as_mut(),
+            Input::Stream(reader) => reader.as_mut(),
+        }
+    }
+
+    /// Length of what is left to read, if it can be known without reading it
+    fn len(&mut self) -> std::io::Result<Option<u64>> {
+        match self {
+            Input::Seekable(reader) => {
+                let position = reader.stream_position()?;
+                let end = reader.seek(SeekFrom::End(0))?;
+                reader.seek(SeekFrom::Start(position))?;
+                Ok(Some(end - position))
             }
-            Ok(n) => {
-                let s = match str::from_utf8(&buf[0..offset + n]) {
-                    Ok(b) => {
-                        offset = 0;
-                        b.to_string()
-                    }
-                    Err(e) => {
-                        let idx = e.valid_up_to();
-
-                        if idx == 0 {
-                            return Err("input is not valid UTF-8".to_string());
-                        }
-                        let r = str::from_utf8(&buf[0..idx]).unwrap().to_string();
-                        let leftover = buf[idx..offset + n].to_owned();
-                        offset = offset + n - idx;
-                        buf[0..offset].copy_from_slice(&leftover);
-                        r
-                    }
-                };
-
-                iter = iter.cont(|inner| inner.cont(|_| s.into_chars()));
-
-                let iter = &mut iter;
-                let mut bytes = Vec::new();
-                let result = Bits8::concat(
-                    iter.map(|x| x.map_err(|e| decoder.map_error(e))),
-                    &mut bytes,
-                );
+            Input::Stream(_) => Ok(None),
+        }
+    }
+
+    /// Read up to `n` bytes ahead, which are then read again
+    fn peek(&mut self, n: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; n];
+        let len = read_full(self.reader(), &mut buf)?;
+        buf.truncate(len);
+        self.unread(buf.clone())?;
+        Ok(buf)
+    }
 
-                match &result {
-                    Ok(()) => {
-                        termination_error = None;
-                    }
-                    Err(ConcatError::Io(..)) => {
-                        panic!("concating to vector should not produce any error")
-                    }
-                    Err(ConcatError::Parent(JimiError::Lexing(
-                        lexing::Error::UnexpectedTermination(..),
-                    )))
-                    | Err(ConcatError::Parent(JimiError::Hajiman(
-                        lexing::Error::UnexpectedTermination(..),
-                    ))) => {
-                        termination_error = Some(result.unwrap_err());
-                    }
-                    Err(ConcatError::Parent(e)) => {
-                        return Err(format!("error parsing honey water: {:?}", e));
-                    }
-                };
-
-                writer
-                    .write(&bytes)
-                    .map_err(|e| format!("error writing output: {}", e))?;
+    /// Put `bytes`, the last read, back in front of the input
+    fn unread(&mut self, bytes: Vec<u8>) -> std::io::Result<()> {
+        match self {
+            Input::Seekable(reader) => {
+                reader.seek(SeekFrom::Current(-(bytes.len() as i64)))?;
             }
-            Err(e) => return Err(format!("read input failed: {}", e)),
+            Input::Stream(reader) => {
+                let rest = std::mem::replace(reader, Box::new(std::io::empty()));
+                *reader = Box::new(Cursor::new(bytes).chain(rest));
+            }
+        }
+        Ok(())
+    }
+
+    fn rewind(&mut self) -> std::io::Result<()> {
+        match self {
+            Input::Seekable(reader) => reader.rewind(),
+            Input::Stream(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "streamed input cannot be read again",
+            )),
+        }
+    }
+
+    /// Make the input seekable, spooling streamed input into memory and then a
+    /// temporary file, returning how many bytes were spooled
+    fn spool(&mut self) -> std::io::Result<Option<u64>> {
+        let Input::Stream(reader) = self else {
+            return Ok(None);
+        };
+        let mut spool = tempfile::SpooledTempFile::new(SPOOL_MEMORY);
+        let n = std::io::copy(reader, &mut spool)?;
+        spool.rewind()?;
+        *self = Input::Seekable(Box::new(BufReader::new(spool)));
+        Ok(Some(n))
+    }
+}
+
+/// Tokens, key and encoding every input is processed with, loaded once
+struct Setup {
+    custom_tokens: Option<LetterIdIndexed<Vec<String>>>,
+    token_set: &'static TokenSet,
+    key: Option<Key>,
+    /// Encoding to use instead of building one for each input, of any width
+    file_header: Option<RawHeader>,
+}
+
+impl Setup {
+    fn new(cli: &Cli) -> Result<Self, CliError> {
+        let custom_tokens = cli
+            .tokens
+            .as_ref()
+            .map(|path| tokens::load_tokens(path))
+            .transpose()?;
+        let token_set = TokenSet::by_name(cli.token_set.as_deref().unwrap_or("hajimi"))
+            .expect("token set names are checked when parsing arguments");
+        let file_header = cli
+            .encoding_file
+            .as_ref()
+            .map(read_encoding_file)
+            .transpose()?;
+        Ok(Self {
+            custom_tokens,
+            token_set,
+            key: cli.key.as_deref().map(Key::new),
+            file_header,
+        })
+    }
+
+    fn new_encoding<B: Bits>(&self, freq: &CharacterFrequency<B>) -> JimiEncoding<B> {
+        match &self.custom_tokens {
+            Some(tokens) => JimiEncoding::with_aliases(tokens.clone(), freq),
+            None => JimiEncoding::from_token_set(self.token_set, freq),
+        }
+    }
+
+    /// `counter` with its counts shuffled if there is a key, so that the encoding built
+    /// from it is as compact once keyed
+    fn shuffle_counter<B: Bits>(&self, counter: CharacterCounter<B>) -> CharacterCounter<B> {
+        match &self.key {
+            Some(key) => CharacterCounter::from_counts(&key.shuffle_counts::<B>(&counter.counts()))
+                .expect("shuffled counts are as many and as big"),
+            None => counter,
         }
     }
+
+    /// Encoding file as a header of `B` symbols, if there is one
+    fn file_header<B>(&self) -> Result<Option<Header<B>>, CliError>
+    where
+        B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.file_header
+            .clone()
+            .map(RawHeader::into_header)
+            .transpose()
+            .map_err(|e| CliError::from(e).context("encoding file"))
+    }
+
+    /// Header of the encoding built from the symbols in `counter`
+    fn counted_header<B>(&self, counter: CharacterCounter<B>) -> Header<B>
+    where
+        B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let counter = self.shuffle_counter(counter);
+        Header::new(self.new_encoding(&counter.finish()))
+            .with_codebook(Codebook::Counts(counter.counts()))
+    }
 }
 
-trait ReadSeek: BufRead + Seek {}
+pub fn run(cli: Cli) -> Result<(), CliError> {
+    match &cli.command {
+        Tokens { command } => return tokens::run(&cli, command),
+        Inspect(args) => return inspect::run(&cli, args),
+        _ => {}
+    }
 
-impl<T> ReadSeek for BufReader<T> where T: Seek + Read {}
-impl<T> ReadSeek for Cursor<T> where T: AsRef<[u8]> {}
+    let setup = Setup::new(&cli)?;
+    match &cli.command {
+        Train(args) => return train::run(&cli, &setup, args),
+        Verify(args) => return verify::run(&cli, &setup, args),
+        Repl => return repl::run(&cli, &setup),
+        _ => {}
+    }
+    if let Some(batch) = cli.batch() {
+        return batch::run(&cli, setup, batch);
+    }
 
-pub fn run(cli: Cli) -> Result<(), String> {
-    let mut input: Box<dyn ReadSeek> = if let Some(input_fpath) = &cli.input_file {
-        let f = std::fs::File::open(input_fpath)
-            .map_err(|e| format!("open file {:?} failed: {}", input_fpath, e))?;
-        Box::new(BufReader::new(f))
+    let input = if let Some(input_fpath) = &cli.input_file {
+        let f =
+            std::fs::File::open(input_fpath).map_err(io(format!("open file {:?}", input_fpath)))?;
+        Input::Seekable(Box::new(BufReader::new(f)))
     } else if let Some(data) = cli.data() {
-        Box::new(Cursor::new(data))
+        Input::Seekable(Box::new(Cursor::new(data.clone())))
     } else {
-        let mut s = String::new();
-        stdin()
-            .read_to_string(&mut s)
-            .map_err(|e| format!("read STDIN failed: {}", e))?;
-        Box::new(Cursor::new(s))
+        Input::Stream(Box::new(stdin().lock()))
     };
 
-    let output: Box<dyn Write> = if let Some(output_fpath) = &cli.output_file {
+    let mut output: Box<dyn Write> = if let Some(output_fpath) = &cli.output_file {
         let f = std::fs::File::create(&output_fpath)
-            .map_err(|e| format!("create file {:?} failed: {}", output_fpath, e))?;
+            .map_err(io(format!("create file {:?}", output_fpath)))?;
         Box::new(BufWriter::new(f))
     } else {
         Box::new(stdout())
     };
 
-    let encoding = if let Some(encoding_file) = &cli.encoding_file {
-        let f = std::fs::File::open(encoding_file)
-            .map_err(|e| format!("open file {:?} failed: {}", encoding_file, e))?;
-        match read_encoding(BufReader::new(f)) {
-            Some(Ok(e)) => e,
-            Some(Err(e)) => {
-                return Err(format!(
-                    "error parsing encoding file {:?}: {}",
-                    encoding_file, e
-                ));
-            }
-            None => {
-                return Err(format!(
-                    "encoding file {:?} does not contain any JSON data",
-                    encoding_file
-                ));
-            }
+    process(&cli, &setup, input, output.as_mut())?;
+    output.flush().map_err(io("write output"))
+}
+
+/// Width of symbols to encode with, that of the encoding file or else `--bits`
+fn encode_width(cli: &Cli, setup: &Setup) -> Result<u32, CliError> {
+    let bits = cli.bits;
+    match (&setup.file_header, bits) {
+        (Some(header), Some(bits)) if header.bits() != bits => Err(CliError::Usage(format!(
+            "encoding file is for {}-bit symbols, but --bits is {}",
+            header.bits(),
+            bits
+        ))),
+        (Some(header), _) => Ok(header.bits()),
+        (None, bits) => Ok(bits.unwrap_or(8)),
+    }
+}
+
+/// Encode, decode or transcode `input` into `output`, with symbols as wide as the
+/// encodings say
+fn process(
+    cli: &Cli,
+    setup: &Setup,
+    mut input: Input,
+    output: &mut dyn Write,
+) -> Result<(), CliError> {
+    match &cli.command {
+        Encode { .. } => with_width!(encode_width(cli, setup)?, B => {
+            encode_input::<B>(cli, setup, input, output)
+        }),
+        Decode { .. } => {
+            let source = read_source(cli, setup, setup.file_header.clone(), &mut input)?;
+            with_width!(source.bits, B => decode_input::<B>(cli, setup, input, source, output))
         }
-    } else {
-        match cli.command {
-            Encode { .. } if cli.frequency_based => {
-                let mut counter = CharacterCounter::empty();
-                count_character(input.as_mut(), &mut counter)
-                    .map_err(|e| format!("read input failed: {}", e))?;
-                let freq = counter.finish();
-
-                input
-                    .seek(std::io::SeekFrom::Start(0))
-                    .map_err(|e| format!("seek input to begin failed: {}", e))?;
-
-                JimiEncoding::new(hajimi_tokens(), &freq)
-            }
-            Encode { .. } => {
-                let freq = CharacterFrequency::all_equal();
-                JimiEncoding::new(hajimi_tokens(), &freq)
-            }
-            Decode { .. } => {
-                if let Some(Ok(enc)) = read_encoding(input.as_mut()) {
-                    skip_until_newline(input.as_mut())?;
-                    enc
-                } else {
-                    let freq = CharacterFrequency::all_equal();
-                    JimiEncoding::new(hajimi_tokens(), &freq)
-                }
-            }
+        Transcode(args) => transcode::run(cli, setup, args, input, output),
+        Train(_) | Inspect(_) | Verify(_) | Repl | Tokens { .. } => unreachable!(),
+    }
+}
+
+/// What honey water is decoded with: the header of the input, if any, and the
+/// encoding to use instead, given or guessed, with the width of their symbols
+struct Source {
+    input_header: Option<RawHeader>,
+    file_header: Option<RawHeader>,
+    bits: u32,
+}
+
+/// Read the header of `input`, and guess its encoding if it has none and nothing in
+/// arguments, such as `file_header`, tells which it is
+fn read_source(
+    cli: &Cli,
+    setup: &Setup,
+    file_header: Option<RawHeader>,
+    input: &mut Input,
+) -> Result<Source, CliError> {
+    // The header of the input decides, then the encoding file, as input without a
+    // header is encoded with it
+    let (input_header, consumed) = RawHeader::read(input.reader())?;
+    input.unread(consumed).map_err(io("read input"))?;
+    let pinned = cli.no_detect
+        || file_header.is_some()
+        || cli.tokens.is_some()
+        || cli.token_set.is_some()
+        || cli.bits.is_some();
+    let file_header = match input_header.is_none() && !pinned {
+        true => guess_encoding(cli, setup, input)?,
+        false => file_header,
+    };
+    let bits = input_header
+        .as_ref()
+        .or(file_header.as_ref())
+        .map(RawHeader::bits)
+        .or(cli.bits)
+        .unwrap_or(8);
+    Ok(Source {
+        input_header,
+        file_header,
+        bits,
+    })
+}
+
+/// Header to decode with, that of the input with the encoding of `source` if it has
+/// one, or else the default encoding
+fn source_header<B>(cli: &Cli, setup: &Setup, source: Source) -> Result<Header<B>, CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let key_flag = key_flag(cli, setup);
+    let file_header = source
+        .file_header
+        .map(RawHeader::into_header::<B>)
+        .transpose()
+        .map_err(|e| CliError::from(e).context("encoding file"))?;
+    let input_header = source
+        .input_header
+        .map(RawHeader::into_header)
+        .transpose()?;
+    if input_header.is_none() {
+        cli.detail("input has no header, decoding with the given or default encoding");
+    }
+    let header = match (file_header, input_header) {
+        (Some(file_header), Some(input_header)) => Header {
+            encoding: file_header.encoding,
+            ..input_header
+        },
+        (Some(file_header), None) => {
+            let mut header = Header::new(file_header.encoding);
+            header.flags.extend(key_flag);
+            header
         }
+        (None, Some(input_header)) => input_header,
+        (None, None) => {
+            let freq = CharacterFrequency::all_equal();
+            let mut header =
+                Header::new(setup.new_encoding(&freq)).with_codebook(Codebook::Uniform);
+            header.flags.extend(key_flag);
+            header
+        }
+    };
+    Ok(finish_header(cli, header))
+}
+
+/// Bytes of input looked at to guess its encoding
+const DETECT_SAMPLE: usize = 64 << 10;
+
+/// Guess which uniform encoding of a built-in token set input without a header is
+/// encoded with, and tell which
+fn guess_encoding(
+    cli: &Cli,
+    setup: &Setup,
+    input: &mut Input,
+) -> Result<Option<RawHeader>, CliError> {
+    let sample = input.peek(DETECT_SAMPLE).map_err(io("read input"))?;
+    // The sample can end in the middle of a character
+    let sample = match std::str::from_utf8(&sample) {
+        Ok(sample) => sample,
+        Err(e) => std::str::from_utf8(&sample[..e.valid_up_to()]).expect("checked to be valid"),
+    };
+    if sample.is_empty() {
+        return Ok(None);
+    }
+
+    let normalization = cli.normalization();
+    let candidates: Vec<DynJimiEncoding> = crate::uniform_candidates()
+        .into_iter()
+        .map(|encoding| encoding.with_normalization(normalization))
+        .collect();
+    // Honey water encoded with a key only decodes with the keyed encodings
+    let keyed: Vec<DynJimiEncoding> = match &setup.key {
+        Some(key) => candidates
+            .iter()
+            .map(|encoding| encoding.keyed(key, cli.key_tokens))
+            .collect(),
+        None => candidates.clone(),
+    };
+    let guesses = crate::rank(sample, &keyed);
+    let Some(best) = guesses.first() else {
+        return Ok(None);
+    };
+    let describe = |guess: &Guess| {
+        let encoding = &candidates[guess.candidate];
+        format!(
+            "{} tokens with {}-bit symbols",
+            encoding.token_set().map_or("custom", TokenSet::name),
+            encoding.bits()
+        )
     };
+    cli.note(format_args!(
+        "input has no header, guessed {} (score {:.3})",
+        describe(best),
+        best.score()
+    ));
+    for guess in &guesses[1..guesses.len().min(4)] {
+        cli.detail(format_args!(
+            "  then {} (score {:.3})",
+            describe(guess),
+            guess.score()
+        ));
+    }
+
+    Ok(Some(match &candidates[best.candidate] {
+        DynJimiEncoding::Bits4(encoding) => uniform_header(encoding),
+        DynJimiEncoding::Bits6(encoding) => uniform_header(encoding),
+        DynJimiEncoding::Bits8(encoding) => uniform_header(encoding),
+    }))
+}
 
-    match cli.command {
-        Encode { .. } => {
+fn uniform_header<B>(encoding: &JimiEncoding<B>) -> RawHeader
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    Header::new(encoding.clone())
+        .with_codebook(Codebook::Uniform)
+        .to_raw()
+}
+
+/// Flag recording that the honey water is encoded with the key, if there is one
+fn key_flag(cli: &Cli, setup: &Setup) -> Option<Flag> {
+    setup.key.as_ref().map(|_| match cli.key_tokens {
+        true => Flag::KeyedTokens,
+        false => Flag::Keyed,
+    })
+}
+
+fn encode_input<B>(
+    cli: &Cli,
+    setup: &Setup,
+    mut input: Input,
+    output: &mut dyn Write,
+) -> Result<(), CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let file_header = setup.file_header::<B>()?;
+    let file_header = file_header.as_ref();
+
+    // Compression and encryption work on the whole payload, which is replaced by
+    // what they turn it into
+    let (compression, encryption) = if cli.compress.is_some() || cli.encrypt || cli.stats {
+        let mut payload = Vec::new();
+        input
+            .reader()
+            .read_to_end(&mut payload)
+            .map_err(io("read input"))?;
+
+        let honey_water_len = |payload: &[u8]| {
+            let encoding = match file_header {
+                Some(header) => header.encoding.clone(),
+                None if cli.frequency_based && !payload.is_empty() => {
+                    let mut counter = CharacterCounter::<B>::empty();
+                    counter.count(B::iter_bytes(payload).data);
+                    setup.new_encoding(&counter.finish())
+                }
+                None => setup.new_encoding(&CharacterFrequency::all_equal()),
+            };
             let encoder = encoding.encoder();
-            encode_enclosing_encoding(
-                input.as_mut(),
-                &encoding,
-                &encoder,
-                output,
-                cli.pretty_encoding,
-            )?;
+            encoder.encode(payload).data.map(str::len).sum::<usize>()
+        };
+        let compression = match cli.compress {
+            None => None,
+            Some(CompressArg::Auto) => Compression::select(&payload, honey_water_len),
+            Some(CompressArg::Deflate) => Some(Compression::Deflate),
+            Some(CompressArg::Zstd) => Some(Compression::Zstd),
+        };
+        if cli.stats {
+            print_stats(&payload, compression, honey_water_len);
         }
-        Decode { .. } => {
-            let decoder = encoding
-                .decoder()
-                .expect("honey water is of course prefix-free");
-            decode(input.as_mut(), &decoder, output)?;
+        if let Some(compression) = compression {
+            let before = payload.len();
+            payload = compression.compress(&payload);
+            cli.detail(format_args!(
+                "compressed {} bytes to {} with {}",
+                before,
+                payload.len(),
+                compression.name()
+            ));
+        }
+
+        let encryption = if cli.encrypt {
+            let passphrase = cli.passphrase()?;
+            let encryption = Encryption::random()?;
+            payload = encryption.encrypt(&passphrase, &payload)?;
+            Some(encryption)
+        } else {
+            None
+        };
+
+        input = Input::Seekable(Box::new(Cursor::new(payload)));
+        (compression, encryption)
+    } else {
+        (None, None)
+    };
+
+    // Counting symbols reads the input twice, and FEC and symbols padded to whole
+    // groups of bytes need its length to tell padding from data
+    let frequency_pass = file_header.is_none() && cli.frequency_based;
+    if (frequency_pass || cli.fec.is_some() || B::group_bytes() > 1)
+        && let Some(n) = input.spool().map_err(io("spool standard input"))?
+    {
+        cli.detail(format_args!("spooled {} bytes of standard input", n));
+    }
+    let length = input.len().map_err(io("seek input"))?;
+
+    let header = match file_header {
+        Some(header) => Header::new(header.encoding.clone()),
+        // There is no frequency to build an encoding from in empty input
+        None if cli.frequency_based && length != Some(0) => {
+            let mut counter = CharacterCounter::empty();
+            count_character(input.reader(), &mut counter).map_err(io("read input"))?;
+            input.rewind().map_err(io("seek input to begin"))?;
+            setup.counted_header(counter)
+        }
+        None => {
+            let freq = CharacterFrequency::all_equal();
+            Header::new(setup.new_encoding(&freq)).with_codebook(Codebook::Uniform)
+        }
+    };
+    let mut header = header
+        .with_length(length)
+        .with_fec(cli.fec)
+        .with_encryption(encryption)
+        .with_compression(compression);
+    if cli.checksum {
+        header.flags.push(Flag::Crc32);
+    }
+    header.flags.extend(key_flag(cli, setup));
+    let header = finish_header(cli, header);
+
+    encode_with_header(
+        input.reader(),
+        &header,
+        setup.key.as_ref(),
+        output,
+        cli.pretty_encoding,
+    )
+}
+
+/// Decode `input` as `source` tells
+fn decode_input<B>(
+    cli: &Cli,
+    setup: &Setup,
+    mut input: Input,
+    source: Source,
+    mut output: &mut dyn Write,
+) -> Result<(), CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let key = setup.key.as_ref();
+    let input_has_header = source.input_header.is_some();
+    let header = source_header::<B>(cli, setup, source)?;
+
+    // Every shard carries its own checksum when there is FEC
+    let checked = header.flags.contains(&Flag::Crc32);
+    if cli.require_checksum && input_has_header && !checked && header.fec.is_none() {
+        return Err(CliError::Checksum(
+            "input carries no checksum, which is required".to_string(),
+        ));
+    }
+    let decoder = header
+        .keyed_encoding(key)
+        .map_err(CliError::Usage)?
+        .decoder()
+        .map_err(|e| CliError::MalformedHeader(format!("tokens cannot be told apart: {:?}", e)))?;
+    let passphrase = header
+        .encryption
+        .as_ref()
+        .map(|_| cli.passphrase())
+        .transpose()?;
+
+    // Encrypted payloads are only written out once authenticated, and compressed
+    // ones once decompressed
+    let whole_payload = header.encryption.is_some() || header.compression.is_some();
+    let mut payload = Vec::new();
+    let writer: &mut dyn Write = match whole_payload {
+        true => &mut payload,
+        false => &mut output,
+    };
+    if let Some(redundancy) = header.fec {
+        let fec = Fec::new(redundancy)?;
+        let stats = decode_fec(input.reader(), &decoder, &fec, writer, header.length)?;
+        if stats.recovered > 0 {
+            cli.note(format_args!("recovered {} damaged shards", stats.recovered));
         }
+    } else {
+        decode(
+            input.reader(),
+            &decoder,
+            writer,
+            header.length,
+            checked || cli.require_checksum,
+        )?;
     }
 
+    if whole_payload {
+        if let (Some(encryption), Some(passphrase)) = (&header.encryption, passphrase) {
+            payload = encryption.decrypt(&passphrase, &payload)?;
+        }
+        if let Some(compression) = header.compression {
+            // The length in the header counts the compressed payload, so it does not
+            // bound what it decompresses to
+            payload = compression.decompress(&payload, None)?;
+        }
+        output
+            .write_all(&payload)
+            .and_then(|_| output.flush())
+            .map_err(io("write output"))?;
+    }
     Ok(())
 }
 
+/// `header` with the normalization asked for recorded in its encoding, described with
+/// `--verbose`
+fn finish_header<B>(cli: &Cli, mut header: Header<B>) -> Header<B>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let normalization = cli.normalization();
+    if !normalization.is_none() {
+        header.encoding = header.encoding.with_normalization(normalization);
+    }
+    cli.detail(describe_header(&header));
+    header
+}
+
 #[cfg(test)]
 mod test {
+    use clap::Parser;
+
     use super::*;
+    use crate::bits::Bits8;
+    use crate::hajimi_tokens;
 
     fn test_inputs() -> Vec<u8> {
         (0u8..200)
@@ -353,7 +1206,7 @@ mod test {
             let mut reader = Cursor::new(&inputs);
             let mut s = Vec::new();
 
-            encode(&mut reader, &encoding.encoder(), &mut s).unwrap();
+            encode(&mut reader, &encoding.encoder(), &mut s, false).unwrap();
 
             String::from_utf8(s).unwrap()
         };
@@ -364,51 +1217,414 @@ mod test {
             let mut reader = Cursor::new(encoded.as_bytes());
             let mut s = Vec::new();
 
-            decode(&mut reader, &encoding.decoder().unwrap(), &mut s).unwrap();
+            decode(
+                &mut reader,
+                &encoding.decoder().unwrap(),
+                &mut s,
+                None,
+                false,
+            )
+            .unwrap();
             s
         };
 
         assert_eq!(inputs.as_slice(), decoded.as_slice());
     }
 
+    #[test]
+    fn test_encode_decode_custom_tokens() {
+        let token_set =
+            tokens::parse_tokens("哈 ha\n基\n米\n曼波\n", tokens::TokenFormat::Lines).unwrap();
+        tokens::validate_tokens(&token_set).unwrap();
+        let freq = CharacterFrequency::<Bits8>::all_equal();
+        let encoding = JimiEncoding::with_aliases(token_set, &freq);
+
+        let inputs = test_inputs();
+        let mut encoded = Vec::new();
+        encode(
+            &mut Cursor::new(&inputs),
+            &encoding.encoder(),
+            &mut encoded,
+            false,
+        )
+        .unwrap();
+        assert!(
+            String::from_utf8(encoded.clone())
+                .unwrap()
+                .chars()
+                .all(|c| "哈基米曼波".contains(c))
+        );
+
+        let mut decoded = Vec::new();
+        decode(
+            &mut Cursor::new(&encoded),
+            &encoding.decoder().unwrap(),
+            &mut decoded,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(&decoded, &inputs);
+    }
+
     #[test]
     fn test_serialize_encoding() {
         let freq = CharacterFrequency::<Bits8>::all_equal();
         let encoding = JimiEncoding::new(hajimi_tokens(), &freq);
 
-        let json = serde_json::to_string_pretty(&encoding).unwrap() + "some trailing data";
-        let parsed = read_encoding(Cursor::new(&json)).unwrap().unwrap();
+        let json = serde_json::to_string_pretty(&encoding).unwrap() + "\nsome trailing data";
+        let parsed = Header::read(Cursor::new(&json)).unwrap().0.unwrap();
 
-        assert_eq!(parsed, encoding);
+        assert_eq!(parsed.encoding, encoding);
     }
 
     #[test]
-    fn test_encode_with_enclosed_encoding_and_decode() {
+    fn test_encode_with_header_and_decode() {
         let freq = CharacterFrequency::<Bits8>::all_equal();
         let encoding = JimiEncoding::new(hajimi_tokens(), &freq);
-        let (encoder, decoder) = (encoding.encoder(), encoding.decoder().unwrap());
+        let decoder = encoding.decoder().unwrap();
 
         let inputs = test_inputs();
+        let header = Header::new(encoding)
+            .with_codebook(Codebook::Uniform)
+            .with_length(Some(inputs.len() as u64));
         let mut encoded = Vec::new();
 
-        encode_enclosing_encoding(
+        encode_with_header(
             &mut Cursor::new(&inputs),
-            &encoding,
-            &encoder,
+            &header,
+            None,
             &mut encoded,
             false,
         )
         .unwrap();
 
         let mut encoded_cursor = Cursor::new(&encoded);
-        let encoding_read = read_encoding(&mut encoded_cursor).unwrap().unwrap();
-        skip_until_newline(&mut encoded_cursor).unwrap();
+        let header_read = Header::read(&mut encoded_cursor).unwrap().0.unwrap();
 
-        assert_eq!(&encoding_read, &encoding);
+        assert_eq!(&header_read, &header);
 
         let mut decoded = Vec::new();
-        decode(&mut encoded_cursor, &decoder, &mut decoded).unwrap();
+        decode(
+            &mut encoded_cursor,
+            &decoder,
+            &mut decoded,
+            header.length,
+            false,
+        )
+        .unwrap();
 
         assert_eq!(&decoded, &inputs);
     }
+
+    #[test]
+    fn test_decode_length() {
+        let freq = CharacterFrequency::<Bits8>::all_equal();
+        let encoding = JimiEncoding::new(hajimi_tokens(), &freq);
+        let decoder = encoding.decoder().unwrap();
+
+        let inputs = test_inputs();
+        let mut encoded = Vec::new();
+        encode(
+            &mut Cursor::new(&inputs),
+            &encoding.encoder(),
+            &mut encoded,
+            false,
+        )
+        .unwrap();
+
+        let mut decoded = Vec::new();
+        decode(
+            &mut Cursor::new(&encoded),
+            &decoder,
+            &mut decoded,
+            Some(10),
+            false,
+        )
+        .unwrap();
+        assert_eq!(&decoded, &inputs[..10]);
+
+        let too_long = Some(inputs.len() as u64 + 1);
+        assert!(
+            decode(
+                &mut Cursor::new(&encoded),
+                &decoder,
+                &mut Vec::new(),
+                too_long,
+                false
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_checksum() {
+        let freq = CharacterFrequency::<Bits8>::all_equal();
+        let encoding = JimiEncoding::new(hajimi_tokens(), &freq);
+        let decoder = encoding.decoder().unwrap();
+
+        let inputs = test_inputs();
+        let mut header = Header::new(encoding).with_codebook(Codebook::Uniform);
+        header.flags.push(Flag::Crc32);
+        let mut encoded = Vec::new();
+        encode_with_header(
+            &mut Cursor::new(&inputs),
+            &header,
+            None,
+            &mut encoded,
+            false,
+        )
+        .unwrap();
+
+        let mut encoded_cursor = Cursor::new(&encoded);
+        let header_read = Header::<Bits8>::read(&mut encoded_cursor)
+            .unwrap()
+            .0
+            .unwrap();
+        assert_eq!(header_read.flags, vec![Flag::Crc32]);
+        let body = &encoded[encoded_cursor.position() as usize..];
+
+        let mut decoded = Vec::new();
+        decode(&mut Cursor::new(body), &decoder, &mut decoded, None, true).unwrap();
+        assert_eq!(&decoded, &inputs);
+
+        let tampered = String::from_utf8(body.to_vec())
+            .unwrap()
+            .replacen("曼波", "哇恰", 1);
+        let e = decode(
+            &mut Cursor::new(tampered),
+            &decoder,
+            &mut Vec::new(),
+            None,
+            true,
+        )
+        .unwrap_err();
+        assert!(e.to_string().starts_with("checksum mismatch"));
+        assert_eq!(e.exit_code(), 6);
+    }
+
+    #[test]
+    fn test_encode_decode_fec() {
+        let freq = CharacterFrequency::<Bits8>::all_equal();
+        let encoding = JimiEncoding::new(hajimi_tokens(), &freq);
+        let decoder = encoding.decoder().unwrap();
+
+        let inputs = test_inputs();
+        let header = Header::new(encoding)
+            .with_codebook(Codebook::Uniform)
+            .with_length(Some(inputs.len() as u64))
+            .with_fec(Some(Redundancy::new(4, 2)));
+        let mut encoded = Vec::new();
+        encode_with_header(
+            &mut Cursor::new(&inputs),
+            &header,
+            None,
+            &mut encoded,
+            false,
+        )
+        .unwrap();
+
+        let mut encoded_cursor = Cursor::new(&encoded);
+        let header_read = Header::<Bits8>::read(&mut encoded_cursor)
+            .unwrap()
+            .0
+            .unwrap();
+        assert_eq!(header_read, header);
+        let body =
+            String::from_utf8(encoded[encoded_cursor.position() as usize..].to_vec()).unwrap();
+
+        // Garble one line, drop another and swap tokens in a third, all in the first block
+        let damaged: Vec<String> = body
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(i, line)| match i {
+                0 => format!("喵{}", line),
+                4 => line.replacen("曼波", "哇恰", 1),
+                _ => line.to_string(),
+            })
+            .collect();
+        let damaged = damaged.join("\n");
+        let fec = Fec::new(Redundancy::new(4, 2)).unwrap();
+
+        let mut decoded = Vec::new();
+        decode_fec(
+            &mut Cursor::new(&body),
+            &decoder,
+            &fec,
+            &mut decoded,
+            header.length,
+        )
+        .unwrap();
+        assert_eq!(&decoded, &inputs);
+
+        assert!(
+            decode_fec(
+                &mut Cursor::new(&damaged),
+                &decoder,
+                &fec,
+                &mut Vec::new(),
+                header.length
+            )
+            .is_err()
+        );
+
+        let recoverable = body
+            .lines()
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(i, line)| match i {
+                0 => format!("喵{}", line),
+                _ => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut decoded = Vec::new();
+        decode_fec(
+            &mut Cursor::new(&recoverable),
+            &decoder,
+            &fec,
+            &mut decoded,
+            header.length,
+        )
+        .unwrap();
+        assert_eq!(&decoded, &inputs);
+    }
+
+    #[test]
+    fn test_bits_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        // Not a whole number of 6-bit groups, so that there is padding to drop
+        let inputs: Vec<u8> = test_inputs().into_iter().take(100).collect();
+        let input = path("in");
+        std::fs::write(&input, &inputs).unwrap();
+
+        let run_args = |args: &[&str]| {
+            let mut argv = vec!["hajiman", "-q"];
+            argv.extend_from_slice(args);
+            run(Cli::try_parse_from(argv).unwrap())
+        };
+        for bits in ["4", "6", "8"] {
+            for options in [&[][..], &["-f", "--checksum"], &["--fec", "4+2"]] {
+                let (encoded, decoded) = (path("encoded"), path("decoded"));
+                let mut args = vec!["--bits", bits, "-i", &input, "-o", &encoded];
+                args.extend_from_slice(options);
+                args.push("encode");
+                run_args(&args).unwrap();
+                let header =
+                    RawHeader::read(BufReader::new(std::fs::File::open(&encoded).unwrap()))
+                        .unwrap()
+                        .0
+                        .unwrap();
+                assert_eq!(header.bits().to_string(), bits);
+
+                run_args(&["-i", &encoded, "-o", &decoded, "decode"]).unwrap();
+                assert_eq!(
+                    std::fs::read(&decoded).unwrap(),
+                    inputs,
+                    "{bits} {options:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_width_of_encoding_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        std::fs::write(path("in"), "哈基米 honey water").unwrap();
+        let run_args = |args: &[&str]| {
+            let mut argv = vec!["hajiman", "-q"];
+            argv.extend_from_slice(args);
+            run(Cli::try_parse_from(argv).unwrap())
+        };
+
+        run_args(&["--bits", "6", "-o", &path("enc"), "train", &path("in")]).unwrap();
+        let e = run_args(&["--bits", "8", "-e", &path("enc"), "encode", "x"]).unwrap_err();
+        assert_eq!(e.exit_code(), 2);
+        let out = path("out");
+        run_args(&["-e", &path("enc"), "-o", &out, "encode", "哈基米"]).unwrap();
+        let header = RawHeader::read(BufReader::new(std::fs::File::open(&out).unwrap()))
+            .unwrap()
+            .0
+            .unwrap();
+        assert_eq!(header.bits(), 6);
+        run_args(&["-i", &out, "-o", &path("decoded"), "decode"]).unwrap();
+        assert_eq!(std::fs::read_to_string(path("decoded")).unwrap(), "哈基米");
+    }
+
+    #[test]
+    fn test_encode_skewed_frequency() {
+        // Frequencies this skewed used to make building the encoding panic
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        let (zeros, out) = (path("zeros"), path("out"));
+        std::fs::write(&zeros, [0; 3000]).unwrap();
+        let run_args = |args: &[&str]| {
+            let mut argv = vec!["hajiman", "-q"];
+            argv.extend_from_slice(args);
+            run(Cli::try_parse_from(argv).unwrap())
+        };
+
+        for bits in ["4", "6", "8"] {
+            let encode = ["-f", "--bits", bits, "--token-set", "emoji", "-i", &zeros];
+            run_args(&[&encode[..], &["-o", &out, "encode"]].concat()).unwrap();
+            run_args(&["-i", &out, "-o", &path("decoded"), "decode"]).unwrap();
+            assert_eq!(std::fs::read(path("decoded")).unwrap(), [0; 3000], "{bits}");
+        }
+    }
+
+    #[test]
+    fn test_guess_encoding() {
+        let honey_water =
+            DynJimiEncoding::from_token_set(6, TokenSet::by_name("emoji").unwrap(), None)
+                .unwrap()
+                .encoder()
+                .encode("哈基米 honey water".as_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("in"), dir.path().join("out"));
+        std::fs::write(&input, &honey_water).unwrap();
+
+        let mut stream = Input::Stream(Box::new(Cursor::new(honey_water.clone())));
+        let cli = Cli::try_parse_from(["hajiman", "-q", "decode"]).unwrap();
+        let setup = Setup::new(&cli).unwrap();
+        let guessed = guess_encoding(&cli, &setup, &mut stream).unwrap().unwrap();
+        assert_eq!(guessed.bits(), 6);
+        let mut read = String::new();
+        stream.reader().read_to_string(&mut read).unwrap();
+        assert_eq!(read, honey_water);
+
+        let decode = |args: &[&str]| {
+            let mut argv = vec!["hajiman", "-q", "-i", input.to_str().unwrap()];
+            argv.extend_from_slice(&["-o", output.to_str().unwrap()]);
+            argv.extend_from_slice(args);
+            argv.push("decode");
+            run(Cli::try_parse_from(argv).unwrap())
+        };
+        decode(&[]).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "哈基米 honey water"
+        );
+        assert!(decode(&["--no-detect"]).is_err());
+    }
+
+    #[test]
+    fn test_spool_stream() {
+        let inputs = test_inputs();
+        let mut input = Input::Stream(Box::new(Cursor::new(inputs.clone())));
+        assert_eq!(input.len().unwrap(), None);
+        assert!(input.rewind().is_err());
+
+        assert_eq!(input.spool().unwrap(), Some(inputs.len() as u64));
+        assert_eq!(input.len().unwrap(), Some(inputs.len() as u64));
+        let mut counter = CharacterCounter::<Bits8>::empty();
+        count_character(input.reader(), &mut counter).unwrap();
+        input.rewind().unwrap();
+        let mut read = Vec::new();
+        input.reader().read_to_end(&mut read).unwrap();
+        assert_eq!(read, inputs);
+        assert_eq!(input.spool().unwrap(), None);
+    }
 }
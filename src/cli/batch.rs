@@ -0,0 +1,268 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Cli, CliError, Command, Input, Setup, count_character, encode_width, io, process};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, CharacterCounter, RawHeader};
+
+/// Extension of encoded files
+const EXTENSION: &str = "jimi";
+
+#[derive(clap::Args)]
+pub struct BatchArgs {
+    #[arg(
+        short,
+        long,
+        value_name = "DIR",
+        requires = "out_dir",
+        conflicts_with = "data"
+    )]
+    /// Process every file under this directory instead of a single input
+    pub recursive: Option<PathBuf>,
+
+    #[arg(long, value_name = "DIR", requires = "recursive")]
+    /// Where files processed with `--recursive` are written, under the same relative
+    /// paths.
+    ///
+    /// Encoded files get a `.jimi` extension, which decoded files lose. Only files with
+    /// that extension are decoded.
+    pub out_dir: Option<PathBuf>,
+
+    #[arg(short, long, value_name = "N", requires = "recursive")]
+    /// Number of files processed at once, by default as many as there are CPUs
+    pub jobs: Option<NonZeroUsize>,
+}
+
+/// Encode or decode every file under `--recursive` into `--out-dir`, reporting each
+/// failure and failing if any file does
+pub fn run(cli: &Cli, mut setup: Setup, args: &BatchArgs) -> Result<(), CliError> {
+    let (Some(root), Some(out_dir)) = (&args.recursive, &args.out_dir) else {
+        unreachable!("--recursive requires --out-dir");
+    };
+    if cli.input_file.is_some() || cli.output_file.is_some() {
+        return Err(CliError::Usage(
+            "--recursive cannot be used with --input-file or --output-file".to_string(),
+        ));
+    }
+    let (decoding, shared_encoding) = match cli.command {
+        Command::Encode {
+            shared_encoding, ..
+        } => (false, shared_encoding),
+        _ => (true, false),
+    };
+
+    let mut files = Vec::new();
+    let skip = out_dir.canonicalize().ok();
+    walk(root, skip.as_deref(), &mut files).map_err(io(format!("list directory {:?}", root)))?;
+    let files: Vec<(PathBuf, PathBuf)> = files
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path.strip_prefix(root).expect("files are under the root");
+            let out = match decoding {
+                true => {
+                    (relative.extension()? == EXTENSION).then(|| relative.with_extension(""))?
+                }
+                false => {
+                    let mut name = relative.as_os_str().to_owned();
+                    name.push(".");
+                    name.push(EXTENSION);
+                    PathBuf::from(name)
+                }
+            };
+            Some((path.clone(), out_dir.join(out)))
+        })
+        .collect();
+
+    if shared_encoding && cli.frequency_based && setup.file_header.is_none() {
+        setup.file_header = with_width!(encode_width(cli, &setup)?, B => {
+            shared_header::<B>(&setup, &files)
+        })?;
+        if setup.file_header.is_some() {
+            cli.detail(format_args!(
+                "built one encoding from {} files",
+                files.len()
+            ));
+        }
+    }
+
+    let jobs = args
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .map_or(1, NonZeroUsize::get);
+    let results = parallel_map(&files, jobs, |(input, output)| {
+        process_file(cli, &setup, input, output)
+    });
+
+    let (done, failed) = match decoding {
+        true => ("decoded", "failed to decode"),
+        false => ("encoded", "failed to encode"),
+    };
+    for ((input, output), result) in files.iter().zip(&results) {
+        match result {
+            Ok(()) => cli.detail(format_args!(
+                "{} {} into {}",
+                done,
+                input.display(),
+                output.display()
+            )),
+            Err(e) => eprintln!("{} {}: {}", failed, input.display(), e),
+        }
+    }
+    let n_failed = results.iter().filter(|result| result.is_err()).count();
+    cli.note(format_args!(
+        "{} {} of {} files, {} failed",
+        done,
+        files.len() - n_failed,
+        files.len(),
+        n_failed
+    ));
+
+    match results.into_iter().find_map(Result::err) {
+        Some(e) => Err(e.with_message(format!("{} of {} files failed", n_failed, files.len()))),
+        None => Ok(()),
+    }
+}
+
+/// Header of the encoding built from the symbols in every file, unless they are empty
+fn shared_header<B>(
+    setup: &Setup,
+    files: &[(PathBuf, PathBuf)],
+) -> Result<Option<RawHeader>, CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut counter = CharacterCounter::<B>::empty();
+    for (path, _) in files {
+        let f = File::open(path).map_err(io(format!("open file {:?}", path)))?;
+        count_character(&mut BufReader::new(f), &mut counter)
+            .map_err(io(format!("read file {:?}", path)))?;
+    }
+    Ok((counter.total() > 0).then(|| setup.counted_header(counter).to_raw()))
+}
+
+fn process_file(cli: &Cli, setup: &Setup, input: &Path, output: &Path) -> Result<(), CliError> {
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(io(format!("create directory {:?}", parent)))?;
+    }
+    let reader = File::open(input).map_err(io(format!("open file {:?}", input)))?;
+    let mut writer =
+        BufWriter::new(File::create(output).map_err(io(format!("create file {:?}", output)))?);
+    let result = process(
+        cli,
+        setup,
+        Input::Seekable(Box::new(BufReader::new(reader))),
+        &mut writer,
+    )
+    .and_then(|()| writer.flush().map_err(io("write output")));
+
+    // Half written output is worse than none
+    if result.is_err() {
+        drop(writer);
+        let _ = std::fs::remove_file(output);
+    }
+    result
+}
+
+/// Files under `dir` in a stable order, leaving out the directory `skip`, which is
+/// where output goes
+pub fn walk(dir: &Path, skip: Option<&Path>, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if skip.is_none() || path.canonicalize().ok().as_deref() != skip {
+                walk(&path, skip, files)?;
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `f` of every item, computed on `jobs` threads
+fn parallel_map<T, R>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    *results[i].lock().unwrap() = Some(f(item));
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| {
+            result
+                .into_inner()
+                .unwrap()
+                .expect("every item is processed")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+
+    fn run_args(args: &[&str]) -> Result<(), CliError> {
+        let mut argv = vec!["hajiman", "--quiet"];
+        argv.extend_from_slice(args);
+        super::super::run(Cli::try_parse_from(argv).unwrap())
+    }
+
+    #[test]
+    fn test_batch_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        std::fs::create_dir_all(path("in/a/b")).unwrap();
+        std::fs::write(path("in/top.txt"), "哈基米 honey water").unwrap();
+        std::fs::write(path("in/a/b/bytes"), (0..=255u8).collect::<Vec<_>>()).unwrap();
+        std::fs::write(path("in/a/empty"), "").unwrap();
+
+        run_args(&[
+            "-f",
+            "encode",
+            "-r",
+            &path("in"),
+            "--out-dir",
+            &path("in/out"),
+            "--shared-encoding",
+        ])
+        .unwrap();
+        assert!(Path::new(&path("in/out/a/b/bytes.jimi")).is_file());
+        assert!(!Path::new(&path("in/out/out")).exists());
+
+        run_args(&["decode", "-r", &path("in/out"), "--out-dir", &path("out")]).unwrap();
+        for file in ["top.txt", "a/b/bytes", "a/empty"] {
+            assert_eq!(
+                std::fs::read(path(&format!("in/{}", file))).unwrap(),
+                std::fs::read(path(&format!("out/{}", file))).unwrap(),
+            );
+        }
+
+        std::fs::write(path("in/out/bad.jimi"), "not honey water").unwrap();
+        let e = run_args(&["decode", "-r", &path("in/out"), "--out-dir", &path("out2")]);
+        assert_eq!(e.unwrap_err().to_string(), "1 of 4 files failed");
+        assert!(!Path::new(&path("out2/bad")).exists());
+        assert!(Path::new(&path("out2/top.txt")).is_file());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    ContainerError, CryptoError, DecompressError, FecError, JimiError, UnsupportedWidth,
+    bits_key::ConcatError,
+};
+
+/// What went wrong running the command line utility, which decides its exit code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliError {
+    /// Arguments or files given cannot be used together or at all
+    Usage(String),
+    /// Reading input or writing output failed
+    Io(String),
+    /// The header of the input, or of the encoding file, cannot be read
+    MalformedHeader(String),
+    /// The honey water cannot be decoded
+    Decode(String),
+    /// The honey water decodes, but a checksum or authentication tag says it is corrupted
+    Checksum(String),
+}
+
+impl CliError {
+    /// Exit code of the process, 2 being also what argument parsing fails with
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => 2,
+            CliError::Io(_) => 3,
+            CliError::MalformedHeader(_) => 4,
+            CliError::Decode(_) => 5,
+            CliError::Checksum(_) => 6,
+        }
+    }
+
+    /// Same kind of error, with `context` in front of the message
+    pub fn context(self, context: impl std::fmt::Display) -> Self {
+        self.map(|e| format!("{}: {}", context, e))
+    }
+
+    /// Same kind of error, with another message
+    pub fn with_message(&self, message: String) -> Self {
+        self.clone().map(|_| message)
+    }
+
+    fn map(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            CliError::Usage(e) => CliError::Usage(f(e)),
+            CliError::Io(e) => CliError::Io(f(e)),
+            CliError::MalformedHeader(e) => CliError::MalformedHeader(f(e)),
+            CliError::Decode(e) => CliError::Decode(f(e)),
+            CliError::Checksum(e) => CliError::Checksum(f(e)),
+        }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Usage(e)
+            | CliError::Io(e)
+            | CliError::MalformedHeader(e)
+            | CliError::Decode(e)
+            | CliError::Checksum(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<ContainerError> for CliError {
+    fn from(value: ContainerError) -> Self {
+        match value {
+            ContainerError::Io(_) => CliError::Io(value.to_string()),
+            ContainerError::UnsupportedVersion(_)
+            | ContainerError::Malformed(_)
+            | ContainerError::ChecksumMismatch { .. }
+            | ContainerError::WidthMismatch { .. } => CliError::MalformedHeader(value.to_string()),
+        }
+    }
+}
+
+impl From<CryptoError> for CliError {
+    fn from(value: CryptoError) -> Self {
+        match value {
+            CryptoError::Random(_) => CliError::Io(value.to_string()),
+            CryptoError::Kdf(_) | CryptoError::BadNonce(_) => {
+                CliError::MalformedHeader(value.to_string())
+            }
+            CryptoError::Authentication => CliError::Checksum(value.to_string()),
+        }
+    }
+}
+
+impl From<FecError> for CliError {
+    fn from(value: FecError) -> Self {
+        match value {
+            FecError::Io(_) => CliError::Io(value.to_string()),
+            FecError::InvalidRedundancy(_) => CliError::Usage(value.to_string()),
+            FecError::TooManyErasures { .. } => CliError::Decode(value.to_string()),
+        }
+    }
+}
+
+impl From<UnsupportedWidth> for CliError {
+    fn from(value: UnsupportedWidth) -> Self {
+        CliError::MalformedHeader(value.to_string())
+    }
+}
+
+impl From<DecompressError> for CliError {
+    fn from(value: DecompressError) -> Self {
+        CliError::Decode(value.to_string())
+    }
+}
+
+impl From<ConcatError<JimiError>> for CliError {
+    fn from(value: ConcatError<JimiError>) -> Self {
+        match value {
+            ConcatError::Parent(JimiError::ChecksumMismatch {
+                expected: Some(expected),
+                found,
+            }) => CliError::Checksum(format!(
+                "checksum mismatch, honey water is corrupted: trailer says {:08x}, data has {:08x}",
+                expected, found
+            )),
+            ConcatError::Parent(JimiError::ChecksumMismatch { expected: None, .. }) => {
+                CliError::Checksum("checksum trailer is missing".to_string())
+            }
+            ConcatError::Parent(e) => {
+                CliError::Decode(format!("error parsing honey water: {:?}", e))
+            }
+            ConcatError::Io(e) => CliError::Io(format!("read input or write output failed: {}", e)),
+        }
+    }
+}
+
+/// Shorthand for I/O errors, with what was being done as context
+pub fn io(context: impl std::fmt::Display) -> impl FnOnce(std::io::Error) -> CliError {
+    move |e| CliError::Io(format!("{} failed: {}", context, e))
+}
@@ -0,0 +1,402 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufReader, Write, stdout};
+use std::path::PathBuf;
+
+use super::{Cli, CliError, io};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, Codebook, Header, Key, LetterCosts, RawHeader, letters::LetterId};
+
+#[derive(clap::Args)]
+pub struct InspectArgs {
+    /// Encoding file, or honey water starting with a header
+    file: PathBuf,
+
+    #[arg(long, value_enum, default_value = "symbol")]
+    /// Order of the rows
+    sort: SortBy,
+
+    #[arg(long, value_enum, default_value = "text")]
+    format: Format,
+
+    #[arg(long, default_value = "false", conflicts_with = "format")]
+    /// Show codes as a tree of letters instead of a table
+    tree: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SortBy {
+    Symbol,
+    /// Cost of the code, shortest first
+    Length,
+    /// Most probable first
+    Probability,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+/// What a codebook says about one symbol
+#[derive(serde::Serialize)]
+struct Row {
+    symbol: usize,
+    code: Vec<usize>,
+    tokens: String,
+    /// Bytes of the tokens
+    cost: usize,
+    probability: f32,
+}
+
+pub fn run(cli: &Cli, args: &InspectArgs) -> Result<(), CliError> {
+    let f = File::open(&args.file).map_err(io(format!("open file {:?}", args.file)))?;
+    let Some(header) = RawHeader::read(BufReader::new(f))?.0 else {
+        return Err(CliError::MalformedHeader(format!(
+            "{:?} has no header or encoding",
+            args.file
+        )));
+    };
+    let key = cli.key.as_deref().map(Key::new);
+    let out = with_width!(header.bits(), B => {
+        let header = header.into_header::<B>()?;
+        if header.keyed().is_some() && key.is_none() {
+            cli.note(format_args!(
+                "codebook is keyed, showing it before permutation; give --key to apply it"
+            ));
+        }
+        inspect::<B>(args, header, key.as_ref())
+    })?;
+
+    let mut output: Box<dyn Write> = match &cli.output_file {
+        Some(path) => Box::new(File::create(path).map_err(io(format!("create file {:?}", path)))?),
+        None => Box::new(stdout()),
+    };
+    output
+        .write_all(out.as_bytes())
+        .and_then(|()| output.flush())
+        .map_err(io("write output"))
+}
+
+/// Table of the codebook of `header`, keyed with `key` if given
+fn inspect<B>(args: &InspectArgs, header: Header<B>, key: Option<&Key>) -> Result<String, CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let encoding = &match key {
+        Some(_) => header.keyed_encoding(key).map_err(CliError::Usage)?,
+        None => header.encoding.clone(),
+    };
+    let tokens = encoding.canonical_tokens();
+    let token_cost = |id: LetterId| tokens[id].len();
+
+    // Probability of each symbol, as counted if the codebook says, or else the one its
+    // code is best for. Keyed codebooks record counts shuffled like their symbols.
+    let counts = match &header.codebook {
+        Codebook::Counts(counts) => Some(match key {
+            Some(key) => key
+                .symbols::<B>()
+                .iter()
+                .map(|(_, to)| counts[to.clone().to_usize()])
+                .collect(),
+            None => counts.clone(),
+        }),
+        Codebook::Full | Codebook::Uniform => None,
+    };
+    let counts = counts.as_ref();
+    let c = LetterCosts::build(tokens.map_by_ref(|id, _| token_cost(id) as i32))
+        .map(|costs| costs.c())
+        .unwrap_or(f32::NAN);
+    let total = counts.map_or(0, |counts| counts.iter().sum::<usize>());
+
+    let mut rows: Vec<Row> = encoding
+        .encoding()
+        .char2code()
+        .iter()
+        .map(|(b, code)| {
+            let symbol = b.to_usize();
+            let cost = code.iter().map(|&id| token_cost(id)).sum();
+            Row {
+                symbol,
+                code: code.iter().map(|id| id.index()).collect(),
+                tokens: code.iter().map(|&id| &tokens[id][..]).collect(),
+                cost,
+                probability: match counts {
+                    Some(counts) => counts[symbol] as f32 / total as f32,
+                    None => c.powi(cost as i32),
+                },
+            }
+        })
+        .collect();
+    match args.sort {
+        SortBy::Symbol => {}
+        SortBy::Length => rows.sort_by_key(|row| (row.cost, row.code.len())),
+        SortBy::Probability => rows.sort_by(|a, b| b.probability.total_cmp(&a.probability)),
+    }
+
+    let mut out = String::new();
+    if args.tree {
+        write_tree::<B>(&mut out, &rows, &tokens.iter().cloned().collect::<Vec<_>>());
+        return Ok(out);
+    }
+    match args.format {
+        Format::Text => {
+            let expected: f32 = rows
+                .iter()
+                .map(|row| row.probability * row.cost as f32)
+                .sum();
+            writeln!(
+                out,
+                "{}-bit symbols, {} letters{}, probabilities {}",
+                B::N,
+                tokens.len(),
+                encoding
+                    .token_set()
+                    .map(|set| format!(" from {}", set.name()))
+                    .unwrap_or_default(),
+                match counts {
+                    Some(_) => "as counted",
+                    None => "implied by costs",
+                }
+            )
+            .unwrap();
+            writeln!(out, "expected cost: {:.3} bytes per symbol", expected).unwrap();
+            writeln!(
+                out,
+                "{:>6}  {:<6}  {:>4}  {:>11}  {:<16}  tokens",
+                "symbol", "char", "cost", "probability", "code"
+            )
+            .unwrap();
+            for row in &rows {
+                let code = row
+                    .code
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(
+                    out,
+                    "{:>6}  {:<6}  {:>4}  {:>11.6}  {:<16}  {}",
+                    row.symbol,
+                    symbol_char::<B>(row.symbol),
+                    row.cost,
+                    row.probability,
+                    code,
+                    row.tokens
+                )
+                .unwrap();
+            }
+        }
+        Format::Csv => {
+            writeln!(out, "symbol,code,tokens,cost,probability").unwrap();
+            for row in &rows {
+                let code = row
+                    .code
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    row.symbol,
+                    code,
+                    csv_field(&row.tokens),
+                    row.cost,
+                    row.probability
+                )
+                .unwrap();
+            }
+        }
+        Format::Json => {
+            out = serde_json::to_string_pretty(&rows).expect("rows serialize");
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// Bytes as they would be written in a character literal, nothing for narrower symbols
+fn symbol_char<B: Bits>(symbol: usize) -> String {
+    match B::N {
+        8 => format!("'{}'", std::ascii::escape_default(symbol as u8)),
+        _ => String::new(),
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Write codes as a tree, each line a letter indented by its depth, and symbols after
+/// the letters ending their code
+fn write_tree<B: Bits>(out: &mut String, rows: &[Row], tokens: &[String]) {
+    let mut codes: Vec<(&[usize], usize)> =
+        rows.iter().map(|row| (&row.code[..], row.symbol)).collect();
+    codes.sort();
+
+    fn write_level<B: Bits>(
+        out: &mut String,
+        codes: &[(&[usize], usize)],
+        depth: usize,
+        tokens: &[String],
+    ) {
+        for group in codes.chunk_by(|a, b| a.0.get(depth) == b.0.get(depth)) {
+            let Some(&letter) = group[0].0.get(depth) else {
+                continue;
+            };
+            write!(out, "{}{}", "  ".repeat(depth), tokens[letter]).unwrap();
+            match group {
+                [(code, symbol)] if code.len() == depth + 1 => {
+                    let char = symbol_char::<B>(*symbol);
+                    match char.is_empty() {
+                        true => writeln!(out, "  {}", symbol),
+                        false => writeln!(out, "  {} {}", symbol, char),
+                    }
+                    .unwrap();
+                }
+                _ => {
+                    writeln!(out).unwrap();
+                    write_level::<B>(out, group, depth + 1, tokens);
+                }
+            }
+        }
+    }
+    write_level::<B>(out, &codes, 0, tokens);
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::bits::{Bits6, Bits8};
+    use crate::{CharacterFrequency, Flag, JimiEncoding, hajimi_tokens};
+
+    fn args(sort: SortBy, format: Format, tree: bool) -> InspectArgs {
+        InspectArgs {
+            file: PathBuf::from("test"),
+            sort,
+            format,
+            tree,
+        }
+    }
+
+    #[test]
+    fn test_inspect() {
+        let mut counts = vec![1; 256];
+        counts[b'a' as usize] = 1000;
+        let freq = crate::CharacterCounter::<Bits8>::from_counts(&counts)
+            .unwrap()
+            .finish();
+        let header = Header::new(JimiEncoding::new(hajimi_tokens(), &freq))
+            .with_codebook(Codebook::Counts(counts));
+
+        let text = inspect(
+            &args(SortBy::Probability, Format::Text, false),
+            header.clone(),
+            None,
+        )
+        .unwrap();
+        let first = text.lines().nth(3).unwrap();
+        assert!(first.trim_start().starts_with("97  'a'"), "{}", first);
+        assert!(text.contains("as counted"));
+
+        let csv = inspect(
+            &args(SortBy::Length, Format::Csv, false),
+            header.clone(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(csv.lines().count(), 257);
+        assert!(csv.lines().nth(1).unwrap().starts_with("97,"));
+
+        let json = inspect(
+            &args(SortBy::Symbol, Format::Json, false),
+            header.clone(),
+            None,
+        )
+        .unwrap();
+        let rows: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(rows[97]["symbol"], 97);
+        assert_eq!(
+            rows[97]["tokens"].as_str().unwrap().len(),
+            rows[97]["cost"].as_u64().unwrap() as usize
+        );
+
+        let tree = inspect(&args(SortBy::Symbol, Format::Text, true), header, None).unwrap();
+        assert_eq!(
+            tree.lines()
+                .filter(|line| line.trim_start().split("  ").count() == 2)
+                .count(),
+            256
+        );
+
+        let uniform = Header::new(JimiEncoding::<Bits6>::new(
+            hajimi_tokens(),
+            &CharacterFrequency::all_equal(),
+        ));
+        let text = inspect(&args(SortBy::Symbol, Format::Text, false), uniform, None).unwrap();
+        assert!(text.contains("implied by costs"));
+    }
+
+    #[test]
+    fn test_inspect_keyed() {
+        let key = Key::new("哈基米");
+        let mut counts = vec![1; 256];
+        counts[b'a' as usize] = 1000;
+        // Counted and keyed as encoding with a key does
+        let shuffled = key.shuffle_counts::<Bits8>(&counts);
+        let freq = crate::CharacterCounter::<Bits8>::from_counts(&shuffled)
+            .unwrap()
+            .finish();
+        let mut header = Header::new(JimiEncoding::new(hajimi_tokens(), &freq))
+            .with_codebook(Codebook::Counts(shuffled));
+        header.flags.push(Flag::Keyed);
+        let keyed = header.keyed_encoding(Some(&key)).unwrap();
+
+        let args = args(SortBy::Symbol, Format::Json, false);
+        let json = inspect(&args, header.clone(), Some(&key)).unwrap();
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        for (b, code) in keyed.encoding().char2code().iter() {
+            let code: Vec<_> = code.iter().map(|id| id.index()).collect();
+            assert_eq!(rows[b.to_usize()]["code"], serde_json::json!(code));
+        }
+        let most_probable = |rows: &[serde_json::Value]| {
+            let probability = |row: &serde_json::Value| row["probability"].as_f64().unwrap();
+            let row = rows
+                .iter()
+                .max_by(|a, b| probability(a).total_cmp(&probability(b)))
+                .unwrap();
+            row["symbol"].clone()
+        };
+        assert_eq!(most_probable(&rows), b'a' as usize);
+
+        // Without the key, the table is the one before permutation
+        let json = inspect(&args, header.clone(), None).unwrap();
+        let rows: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_ne!(most_probable(&rows), b'a' as usize);
+
+        header.flags.clear();
+        assert!(matches!(
+            inspect(&args, header, Some(&key)),
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn test_inspect_headerless() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("honey water");
+        std::fs::write(&file, "哈基米曼波").unwrap();
+        let argv = ["hajiman", "inspect", file.to_str().unwrap()];
+        let result = super::super::run(Cli::try_parse_from(argv).unwrap());
+        assert!(matches!(result, Err(CliError::MalformedHeader(_))));
+    }
+}
@@ -0,0 +1,363 @@
+use std::io::{BufRead, IsTerminal, Write, stdin, stdout};
+use std::path::Path;
+
+use super::{Cli, CliError, Setup, io, read_encoding_file, tokens};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, DynJimiEncoding, JimiEncoding, RawHeader, TokenSet, letters::LetterIdIndexed};
+
+const HELP: &str = "\
+Each line is decoded if it lexes as honey water, and encoded otherwise.
+
+  :load <file>     use the encoding of an encoding file or header
+  :tokens [name]   list tokens, or switch to a token set or tokens file
+  :bits <n>        encode with symbols n bits wide
+  :train <file>    build the encoding from the frequency of symbols in a file
+  :show <byte>     show the code and tokens of a byte, as a number or character
+  :stats           show the encoding and what was encoded and decoded so far
+  :help            show this help
+  :quit            leave, as does the end of input";
+
+/// What is kept between lines
+struct Repl<'a> {
+    cli: &'a Cli,
+    setup: &'a Setup,
+    /// Encoding as loaded or built, before the key and normalization are applied
+    encoding: DynJimiEncoding,
+    /// Where the encoding comes from
+    origin: String,
+    /// Bytes counted by `:train`, which the encoding is built from again when its
+    /// tokens or width change
+    sample: Option<Vec<u8>>,
+    lines_encoded: usize,
+    lines_decoded: usize,
+    plain_bytes: usize,
+    honey_water_bytes: usize,
+}
+
+pub fn run(cli: &Cli, setup: &Setup) -> Result<(), CliError> {
+    let interactive = stdin().is_terminal();
+    if interactive {
+        eprintln!("hajiman repl, :help lists commands");
+    }
+    repl(cli, setup, stdin().lock(), stdout(), interactive)
+}
+
+/// Answer each line of `input` on `output`, prompting on standard error if
+/// `interactive`
+fn repl(
+    cli: &Cli,
+    setup: &Setup,
+    input: impl BufRead,
+    mut output: impl Write,
+    interactive: bool,
+) -> Result<(), CliError> {
+    let mut repl = Repl::new(cli, setup)?;
+    let mut lines = input.lines();
+    loop {
+        if interactive {
+            eprint!("hajiman> ");
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(io("read input"))?;
+        let line = line.trim();
+        let answer = match line.strip_prefix(':') {
+            Some(command) => {
+                let (command, arg) = command.split_once(' ').unwrap_or((command, ""));
+                match command {
+                    "quit" | "q" => return Ok(()),
+                    _ => repl.command(command, arg.trim()),
+                }
+            }
+            None if line.is_empty() => continue,
+            None => repl.line(line),
+        };
+        // Mistakes are told, and the session goes on
+        match answer {
+            Ok(answer) => writeln!(output, "{}", answer)
+                .and_then(|()| output.flush())
+                .map_err(io("write output"))?,
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+}
+
+/// Tokens to build an encoding with
+enum Tokens {
+    Set(&'static TokenSet),
+    Custom(LetterIdIndexed<Vec<String>>),
+}
+
+/// Encoding an encoding file or header records
+fn header_encoding(header: RawHeader) -> Result<DynJimiEncoding, CliError> {
+    with_width!(header.bits(), B => {
+        Ok(DynJimiEncoding::from(header.into_header::<B>()?.encoding))
+    })
+}
+
+impl<'a> Repl<'a> {
+    fn new(cli: &'a Cli, setup: &'a Setup) -> Result<Self, CliError> {
+        let bits = cli.bits.unwrap_or(8);
+        let encoding = match (&setup.file_header, &setup.custom_tokens) {
+            (Some(header), _) => header_encoding(header.clone())?,
+            (None, Some(tokens)) => DynJimiEncoding::with_aliases(bits, tokens.clone(), None)
+                .map_err(|e| CliError::Usage(e.to_string()))?,
+            (None, None) => DynJimiEncoding::from_token_set(bits, setup.token_set, None)
+                .map_err(|e| CliError::Usage(e.to_string()))?,
+        };
+        Ok(Self {
+            cli,
+            setup,
+            encoding,
+            origin: match setup.file_header {
+                Some(_) => "loaded from the encoding file".to_string(),
+                None => "uniform".to_string(),
+            },
+            sample: None,
+            lines_encoded: 0,
+            lines_decoded: 0,
+            plain_bytes: 0,
+            honey_water_bytes: 0,
+        })
+    }
+
+    /// The encoding lines are encoded and decoded with
+    fn effective(&self) -> DynJimiEncoding {
+        let mut encoding = self.encoding.clone();
+        let normalization = self.cli.normalization();
+        if !normalization.is_none() {
+            encoding = encoding.with_normalization(normalization);
+        }
+        match &self.setup.key {
+            Some(key) => encoding.keyed(key, self.cli.key_tokens),
+            None => encoding,
+        }
+    }
+
+    /// Encoding of `tokens` with `bits` wide symbols, as frequent as in the sample if
+    /// there is one
+    fn rebuild(&mut self, bits: u32, tokens: Tokens) -> Result<(), CliError> {
+        let sample = self.sample.as_deref();
+        self.encoding = match tokens {
+            Tokens::Set(set) => DynJimiEncoding::from_token_set(bits, set, sample),
+            Tokens::Custom(tokens) => DynJimiEncoding::with_aliases(bits, tokens, sample),
+        }
+        .map_err(|e| CliError::Usage(e.to_string()))?;
+        self.origin = match sample {
+            Some(sample) => format!("trained on {} bytes", sample.len()),
+            None => "uniform".to_string(),
+        };
+        Ok(())
+    }
+
+    fn current_tokens(&self) -> Tokens {
+        match self.encoding.token_set() {
+            Some(set) => Tokens::Set(set),
+            None => Tokens::Custom(self.encoding.tokens().clone()),
+        }
+    }
+
+    fn command(&mut self, command: &str, arg: &str) -> Result<String, CliError> {
+        let needs_arg = |what: &str| match arg {
+            "" => Err(CliError::Usage(format!(":{} needs {}", command, what))),
+            arg => Ok(arg),
+        };
+        match command {
+            "help" | "h" => Ok(HELP.to_string()),
+            "load" => {
+                let path = needs_arg("an encoding file")?;
+                self.encoding = header_encoding(read_encoding_file(&path.into())?)?;
+                self.origin = format!("loaded from {:?}", path);
+                self.sample = None;
+                Ok(self.describe())
+            }
+            "tokens" if arg.is_empty() => Ok(self
+                .encoding
+                .tokens()
+                .iter_with_id()
+                .map(|(id, spellings)| format!("{:>3}  {}", id.index(), spellings.join("  ")))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "tokens" => {
+                let tokens = match TokenSet::by_name(arg) {
+                    Some(set) => Tokens::Set(set),
+                    None => Tokens::Custom(tokens::load_tokens(Path::new(arg))?),
+                };
+                self.rebuild(self.encoding.bits(), tokens)?;
+                Ok(self.describe())
+            }
+            "bits" => {
+                let bits = needs_arg("a width")?;
+                let bits = bits
+                    .parse()
+                    .map_err(|_| CliError::Usage(format!("{:?} is not a width", bits)))?;
+                self.rebuild(bits, self.current_tokens())?;
+                Ok(self.describe())
+            }
+            "train" => {
+                let path = needs_arg("a file")?;
+                let sample = std::fs::read(path).map_err(io(format!("read file {:?}", path)))?;
+                self.sample = Some(sample);
+                self.rebuild(self.encoding.bits(), self.current_tokens())?;
+                Ok(self.describe())
+            }
+            "show" => {
+                let byte = parse_byte(needs_arg("a byte")?)?;
+                match &self.effective() {
+                    DynJimiEncoding::Bits4(encoding) => Ok(show(encoding, byte)),
+                    DynJimiEncoding::Bits6(encoding) => Ok(show(encoding, byte)),
+                    DynJimiEncoding::Bits8(encoding) => Ok(show(encoding, byte)),
+                }
+            }
+            "stats" => Ok(format!(
+                "{}\nencoded {} lines, decoded {}: {} bytes as {} bytes of honey water, {:.3}x",
+                self.describe(),
+                self.lines_encoded,
+                self.lines_decoded,
+                self.plain_bytes,
+                self.honey_water_bytes,
+                self.honey_water_bytes as f32 / self.plain_bytes.max(1) as f32
+            )),
+            _ => Err(CliError::Usage(format!(
+                "unknown command :{}, :help lists them",
+                command
+            ))),
+        }
+    }
+
+    /// Decode `line` if it is honey water, or else encode it
+    fn line(&mut self, line: &str) -> Result<String, CliError> {
+        let encoding = self.effective();
+        let decoder = encoding
+            .decoder()
+            .map_err(|e| CliError::Usage(format!("tokens cannot be told apart: {:?}", e)))?;
+        // Symbols short of a group decode to nothing, which is no honey water
+        match decoder.decode(line, None) {
+            Ok(mut bytes) if !bytes.is_empty() => {
+                // Without a length, zero bytes pad the last group of symbols
+                let group = with_width!(encoding.bits(), B => Ok::<_, CliError>(B::group_bytes()))?;
+                let padding = bytes
+                    .iter()
+                    .rev()
+                    .take(group - 1)
+                    .take_while(|&&b| b == 0)
+                    .count();
+                bytes.truncate(bytes.len() - padding);
+                self.lines_decoded += 1;
+                self.plain_bytes += bytes.len();
+                self.honey_water_bytes += line.len();
+                Ok(match String::from_utf8(bytes) {
+                    Ok(text) => text,
+                    Err(e) => e.as_bytes().escape_ascii().to_string(),
+                })
+            }
+            _ => {
+                let honey_water = encoding.encoder().encode(line.as_bytes());
+                self.lines_encoded += 1;
+                self.plain_bytes += line.len();
+                self.honey_water_bytes += honey_water.len();
+                Ok(honey_water)
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} tokens with {}-bit symbols, {}",
+            self.encoding.token_set().map_or("custom", TokenSet::name),
+            self.encoding.bits(),
+            self.origin
+        )
+    }
+}
+
+/// Byte given as a decimal or `0x` hexadecimal number, or as an ASCII character
+fn parse_byte(s: &str) -> Result<u8, CliError> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None if s.len() == 1 && !s.as_bytes()[0].is_ascii_digit() => Some(s.as_bytes()[0]),
+        None => s.parse().ok(),
+    };
+    parsed.ok_or_else(|| CliError::Usage(format!("{:?} is not a byte", s)))
+}
+
+/// Code and tokens of each symbol `byte` is made of, and its honey water
+fn show<B: Bits>(encoding: &JimiEncoding<B>, byte: u8) -> String {
+    let tokens = encoding.canonical_tokens();
+    let char2code = encoding.encoding().char2code();
+    // Only the symbols the byte spills into, not those padding their group
+    let bytes = [byte];
+    let symbols = B::iter_bytes(&bytes)
+        .data
+        .take(8usize.div_ceil(B::N as usize));
+    let mut lines = Vec::new();
+    let mut honey_water = String::new();
+    for symbol in symbols {
+        let n = symbol.clone().to_usize();
+        let code = &char2code[symbol];
+        let code_tokens: String = code.iter().map(|&id| &tokens[id][..]).collect();
+        lines.push(format!(
+            "  symbol {:>3}: code {:?} -> {}",
+            n,
+            code.iter().map(|id| id.index()).collect::<Vec<_>>(),
+            code_tokens
+        ));
+        honey_water += &code_tokens;
+    }
+    format!(
+        "{:#04x} {:?}: {}\n{}",
+        byte,
+        byte as char,
+        honey_water,
+        lines.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_repl() {
+        let dir = tempfile::tempdir().unwrap();
+        let sample = dir.path().join("sample");
+        std::fs::write(&sample, "honey water ".repeat(20)).unwrap();
+
+        let cli = Cli::try_parse_from(["hajiman", "--bits", "6", "repl"]).unwrap();
+        let setup = Setup::new(&cli).unwrap();
+        let mut repl = Repl::new(&cli, &setup).unwrap();
+        let honey_water = repl.line("哈基米 honey water").unwrap();
+        assert_eq!(repl.line(&honey_water).unwrap(), "哈基米 honey water");
+
+        repl.command("tokens", "morse").unwrap();
+        repl.command("train", sample.to_str().unwrap()).unwrap();
+        repl.command("bits", "4").unwrap();
+        let honey_water = repl.line("abc").unwrap();
+        assert_eq!(repl.line(&honey_water).unwrap(), "abc");
+        assert!(repl.command("bits", "5").is_err());
+        assert!(repl.command("nope", "").is_err());
+        assert_eq!(
+            repl.command("stats", "").unwrap().lines().last().unwrap(),
+            format!(
+                "encoded 2 lines, decoded 2: 48 bytes as {} bytes of honey water, {:.3}x",
+                repl.honey_water_bytes,
+                repl.honey_water_bytes as f32 / 48.0
+            )
+        );
+
+        let show = repl.command("show", "0x61").unwrap();
+        assert!(show.starts_with("0x61 'a': "));
+        assert_eq!(show.lines().count(), 3);
+        assert_eq!(parse_byte("a").unwrap(), parse_byte("97").unwrap());
+        assert!(parse_byte("256").is_err());
+
+        // Lines are answered until the end of input or :quit
+        let mut output = Vec::new();
+        let input = "hello\n\n:bits x\n:quit\nnot answered\n".as_bytes();
+        super::repl(&cli, &setup, input, &mut output, false).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 1);
+    }
+}
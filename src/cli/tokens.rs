@@ -0,0 +1,465 @@
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{Write as _, stdout};
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+
+use super::{Cli, CliError, io};
+use crate::{
+    Bits, CharacterCounter, CharacterFrequency, JimiEncoding, LetterCosts, StringLexer, TokenSet,
+    bits::Bits8, letters::LetterIdIndexed,
+};
+
+#[derive(Subcommand)]
+pub enum TokensCommand {
+    /// List built-in token sets.
+    List,
+    /// Check whether a token set can be used, and how much data it carries.
+    Check {
+        /// File listing tokens, in any format accepted by `--tokens`
+        file: PathBuf,
+
+        #[arg(short, long)]
+        /// Also estimate expansion for the frequency of bytes in this file
+        sample: Option<PathBuf>,
+    },
+}
+
+pub fn run(cli: &Cli, command: &TokensCommand) -> Result<(), CliError> {
+    match command {
+        TokensCommand::List => {
+            let mut out = String::new();
+            list_token_sets(&mut out);
+            write_output(cli, &out)
+        }
+        TokensCommand::Check { file, sample } => {
+            let tokens = read_tokens(file)?;
+            let sample = sample
+                .as_ref()
+                .map(|path| {
+                    let bytes = std::fs::read(path).map_err(io(format!("read file {:?}", path)))?;
+                    if bytes.is_empty() {
+                        return Err(CliError::Usage(format!("sample file {:?} is empty", path)));
+                    }
+                    let freq = CharacterCounter::empty()
+                        .count(Bits8::iter_bytes(&bytes).data)
+                        .finish();
+                    Ok((path.display().to_string(), freq))
+                })
+                .transpose()?;
+
+            let mut report = String::new();
+            let usable = check_tokens(&tokens, sample.as_ref(), &mut report);
+            write_output(cli, &report)?;
+
+            if usable {
+                Ok(())
+            } else {
+                Err(CliError::Usage(format!(
+                    "token set in {:?} cannot be used",
+                    file
+                )))
+            }
+        }
+    }
+}
+
+/// Write `out` to the output file, or else standard output
+fn write_output(cli: &Cli, out: &str) -> Result<(), CliError> {
+    let mut output: Box<dyn std::io::Write> = match &cli.output_file {
+        Some(path) => Box::new(File::create(path).map_err(io(format!("create file {:?}", path)))?),
+        None => Box::new(stdout()),
+    };
+    output
+        .write_all(out.as_bytes())
+        .and_then(|()| output.flush())
+        .map_err(io("write output"))
+}
+
+/// Formats token files may be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// One letter per line, aliases following the token separated by spaces
+    Lines,
+    /// A list whose elements are tokens or lists of aliases
+    Json,
+    /// A `tokens` key holding a list like the JSON one
+    Toml,
+}
+
+impl TokenFormat {
+    /// Guess format from file extension, then from content
+    fn detect(path: &Path, s: &str) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => TokenFormat::Json,
+            Some("toml") => TokenFormat::Toml,
+            _ if s.trim_start().starts_with('[') => TokenFormat::Json,
+            _ if s
+                .parse::<toml::Table>()
+                .is_ok_and(|t| t.contains_key("tokens")) =>
+            {
+                TokenFormat::Toml
+            }
+            _ => TokenFormat::Lines,
+        }
+    }
+}
+
+pub fn read_tokens(path: &Path) -> Result<LetterIdIndexed<Vec<String>>, CliError> {
+    let s = std::fs::read_to_string(path).map_err(io(format!("read token file {:?}", path)))?;
+    parse_tokens(&s, TokenFormat::detect(path, &s))
+        .map_err(|e| CliError::Usage(format!("error parsing token file {:?}: {}", path, e)))
+}
+
+/// Read tokens from `path`, making sure an encoding can be built from them
+pub fn load_tokens(path: &Path) -> Result<LetterIdIndexed<Vec<String>>, CliError> {
+    let tokens = read_tokens(path)?;
+    validate_tokens(&tokens)
+        .map_err(|e| CliError::Usage(format!("token file {:?} cannot be used: {}", path, e)))?;
+    Ok(tokens)
+}
+
+pub fn parse_tokens(s: &str, format: TokenFormat) -> Result<LetterIdIndexed<Vec<String>>, String> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Spellings {
+        Canonical(String),
+        Aliases(Vec<String>),
+    }
+
+    #[derive(serde::Deserialize)]
+    struct TomlTokens {
+        tokens: Vec<Spellings>,
+    }
+
+    let spellings = match format {
+        TokenFormat::Json => {
+            Some(serde_json::from_str::<Vec<Spellings>>(s).map_err(|e| e.to_string())?)
+        }
+        TokenFormat::Toml => Some(
+            toml::from_str::<TomlTokens>(s)
+                .map_err(|e| e.to_string())?
+                .tokens,
+        ),
+        TokenFormat::Lines => None,
+    };
+
+    let tokens: Vec<Vec<String>> = if let Some(spellings) = spellings {
+        spellings
+            .into_iter()
+            .map(|spellings| match spellings {
+                Spellings::Canonical(s) => vec![s],
+                Spellings::Aliases(v) => v,
+            })
+            .collect()
+    } else {
+        s.lines()
+            .map(|line| {
+                line.split_whitespace()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|spellings| !spellings.is_empty())
+            .collect()
+    };
+
+    if let Some(i) = tokens.iter().position(|spellings| spellings.is_empty()) {
+        return Err(format!("letter {} has no spelling", i));
+    }
+    if let Some(i) = tokens
+        .iter()
+        .position(|spellings| spellings.iter().any(|s| s.is_empty()))
+    {
+        return Err(format!("letter {} has an empty spelling", i));
+    }
+
+    Ok(LetterIdIndexed::new(tokens))
+}
+
+/// Check that an encoding can be built from `tokens` and decoded unambiguously
+pub fn validate_tokens(tokens: &LetterIdIndexed<Vec<String>>) -> Result<(), String> {
+    if tokens.len() < 2 {
+        return Err(format!(
+            "at least two letters are needed, found {}",
+            tokens.len()
+        ));
+    }
+    if let Err(e) = StringLexer::with_aliases(tokens, Default::default()) {
+        return Err(match prefix_conflict(tokens) {
+            Some((a, b)) => format!("{:?} is a prefix of {:?}", a, b),
+            None => format!("tokens cannot be told apart: {:?}", e),
+        });
+    }
+    LetterCosts::build(tokens.map_by_ref(|_, s| s[0].len() as i32))
+        .map_err(|_| "cannot solve the characteristic equation for costs".to_string())?;
+    Ok(())
+}
+
+/// Find two spellings, one of which is a prefix of the other
+fn prefix_conflict(tokens: &LetterIdIndexed<Vec<String>>) -> Option<(&str, &str)> {
+    let spellings = tokens
+        .iter_with_id()
+        .flat_map(|(id, spellings)| spellings.iter().map(move |s| (id, s)))
+        .collect::<Vec<_>>();
+
+    spellings.iter().enumerate().find_map(|(i, (id, a))| {
+        spellings[i + 1..]
+            .iter()
+            .filter(|(other, b)| other != id || a != b)
+            .find_map(|(_, b)| {
+                if b.starts_with(a.as_str()) {
+                    Some((a.as_str(), b.as_str()))
+                } else if a.starts_with(b.as_str()) {
+                    Some((b.as_str(), a.as_str()))
+                } else {
+                    None
+                }
+            })
+    })
+}
+
+/// Expected output bytes for each input byte
+pub fn expansion<B: Bits>(encoding: &JimiEncoding<B>, freq: &CharacterFrequency<B>) -> f32 {
+    let encoder = encoding.encoder();
+    let per_symbol: f32 = crate::BitsIter::<B>::begin_zero()
+        .map(|b| freq.freq(b.clone()) * encoder.encode_bits(b).len() as f32)
+        .sum();
+    per_symbol / (B::N as f32 / 8.0)
+}
+
+/// Least expected output bytes for each input byte any code could achieve
+fn ideal_expansion(freq: &CharacterFrequency<Bits8>, bits_per_byte: f32) -> f32 {
+    let entropy: f32 = crate::BitsIter::<Bits8>::begin_zero()
+        .map(|b| freq.freq(b))
+        .filter(|p| *p > 0.0)
+        .map(|p| -p * p.log2())
+        .sum();
+    entropy / bits_per_byte / (Bits8::N as f32 / 8.0)
+}
+
+/// Write a report on `tokens` to `out`, returning whether they can be used
+fn check_tokens(
+    tokens: &LetterIdIndexed<Vec<String>>,
+    sample: Option<&(String, CharacterFrequency<Bits8>)>,
+    out: &mut String,
+) -> bool {
+    let mut usable = true;
+
+    writeln!(out, "letters: {}", tokens.len()).unwrap();
+    for (i, spellings) in tokens.iter().enumerate() {
+        writeln!(
+            out,
+            "  {:>3}  {}  ({} bytes, {} chars){}",
+            i,
+            spellings[0],
+            spellings[0].len(),
+            spellings[0].chars().count(),
+            if spellings.len() > 1 {
+                format!("  aliases: {}", spellings[1..].join(" "))
+            } else {
+                String::new()
+            }
+        )
+        .unwrap();
+    }
+
+    match StringLexer::with_aliases(tokens, Default::default()) {
+        Ok(lexer) => {
+            writeln!(out, "prefix-free: yes").unwrap();
+
+            let mut chars = lexer.significant_chars().collect::<Vec<_>>();
+            chars.sort();
+            writeln!(
+                out,
+                "significant chars ({}): {}",
+                chars.len(),
+                chars.into_iter().collect::<String>()
+            )
+            .unwrap();
+        }
+        Err(e) => {
+            usable = false;
+            match prefix_conflict(tokens) {
+                Some((a, b)) => writeln!(out, "prefix-free: no, {:?} is a prefix of {:?}", a, b),
+                None => writeln!(out, "prefix-free: no, {:?}", e),
+            }
+            .unwrap();
+        }
+    }
+
+    if tokens.len() < 2 {
+        writeln!(out, "at least two letters are needed to encode anything").unwrap();
+        return false;
+    }
+
+    let byte_costs = LetterCosts::build(tokens.map_by_ref(|_, s| s[0].len() as i32));
+    let char_costs = LetterCosts::build(tokens.map_by_ref(|_, s| s[0].chars().count() as i32));
+    let (byte_costs, char_costs) = match (byte_costs, char_costs) {
+        (Ok(b), Ok(c)) => (b, c),
+        _ => {
+            writeln!(out, "cannot solve the characteristic equation for costs").unwrap();
+            return false;
+        }
+    };
+
+    let bits_per_byte = -byte_costs.c().log2();
+    let bits_per_char = -char_costs.c().log2();
+    writeln!(out, "root c: {:.6} (costs in bytes)", byte_costs.c()).unwrap();
+    writeln!(
+        out,
+        "capacity: {:.4} bits per byte, {:.4} bits per char",
+        bits_per_byte, bits_per_char
+    )
+    .unwrap();
+
+    let uniform = CharacterFrequency::all_equal();
+    let mut frequencies = vec![("uniform".to_string(), &uniform)];
+    frequencies.extend(sample.map(|(name, freq)| (name.clone(), freq)));
+
+    for (name, freq) in frequencies {
+        let encoding = JimiEncoding::with_aliases(tokens.clone(), freq);
+        writeln!(
+            out,
+            "expansion ({}): {:.3} bytes per input byte, ideally {:.3}",
+            name,
+            expansion(&encoding, freq),
+            ideal_expansion(freq, bits_per_byte)
+        )
+        .unwrap();
+    }
+
+    usable
+}
+
+fn list_token_sets(out: &mut String) {
+    for set in TokenSet::all() {
+        let tokens = set.tokens();
+        writeln!(
+            out,
+            "{:<16}{:>3} letters  {}",
+            set.name(),
+            tokens.len(),
+            set.description()
+        )
+        .unwrap();
+        writeln!(
+            out,
+            "{:<16}{}",
+            "",
+            tokens
+                .iter()
+                .map(|spellings| spellings.join("|"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::hajimi_tokens;
+
+    #[test]
+    fn test_parse_tokens() {
+        let expected = LetterIdIndexed::new(vec![
+            vec!["哈基米".to_string(), "哈基咪".to_string()],
+            vec!["曼波".to_string()],
+        ]);
+
+        assert_eq!(
+            parse_tokens("哈基米 哈基咪\n\n曼波\n", TokenFormat::Lines).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_tokens(r#"[["哈基米", "哈基咪"], "曼波"]"#, TokenFormat::Json).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_tokens(
+                "# vocabulary\ntokens = [[\"哈基米\", \"哈基咪\"], \"曼波\"]\n",
+                TokenFormat::Toml
+            )
+            .unwrap(),
+            expected
+        );
+        assert!(parse_tokens(r#"[[], "曼波"]"#, TokenFormat::Json).is_err());
+    }
+
+    #[test]
+    fn test_check_hajimi_tokens() {
+        let mut report = String::new();
+        let tokens = hajimi_tokens().map(|_, s| vec![s]);
+        assert!(check_tokens(&tokens, None, &mut report));
+        assert!(report.contains("prefix-free: yes"));
+        assert!(report.contains("expansion (uniform)"));
+    }
+
+    #[test]
+    fn test_check_non_prefix_free_tokens() {
+        let mut report = String::new();
+        let tokens = parse_tokens("哈基米\n曼波\n哈基", TokenFormat::Lines).unwrap();
+        assert!(!check_tokens(&tokens, None, &mut report));
+        assert!(report.contains(r#""哈基" is a prefix of "哈基米""#));
+        assert_eq!(
+            validate_tokens(&tokens),
+            Err(r#""哈基" is a prefix of "哈基米""#.to_string())
+        );
+    }
+
+    #[test]
+    fn test_list_token_sets() {
+        let mut out = String::new();
+        list_token_sets(&mut out);
+        assert!(out.starts_with("hajimi"));
+        assert!(out.contains("哈基米 那呗路多"));
+    }
+
+    #[test]
+    fn test_output_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let out = out.to_str().unwrap();
+        let hajiman = |argv: &[&str]| super::super::run(Cli::try_parse_from(argv).unwrap());
+
+        hajiman(&["hajiman", "-o", out, "tokens", "list"]).unwrap();
+        assert!(std::fs::read_to_string(out).unwrap().starts_with("hajimi"));
+
+        let tokens = dir.path().join("tokens.txt");
+        std::fs::write(&tokens, "哈基米\n曼波\n").unwrap();
+        let tokens = tokens.to_str().unwrap();
+        hajiman(&["hajiman", "-o", out, "tokens", "check", tokens]).unwrap();
+        let report = std::fs::read_to_string(out).unwrap();
+        assert!(report.contains("prefix-free: yes"));
+
+        // A directory cannot be written to
+        let dir = dir.path().to_str().unwrap();
+        let result = hajiman(&["hajiman", "-o", dir, "tokens", "list"]);
+        assert!(matches!(result, Err(CliError::Io(_))));
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let path = Path::new("tokens.txt");
+        assert_eq!(
+            TokenFormat::detect(path, "哈基米\n曼波"),
+            TokenFormat::Lines
+        );
+        assert_eq!(
+            TokenFormat::detect(path, " [\"哈基米\"]"),
+            TokenFormat::Json
+        );
+        assert_eq!(
+            TokenFormat::detect(path, "tokens = [\"哈基米\"]"),
+            TokenFormat::Toml
+        );
+        assert_eq!(
+            TokenFormat::detect(Path::new("tokens.toml"), ""),
+            TokenFormat::Toml
+        );
+    }
+}
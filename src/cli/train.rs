@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write, stdout};
+use std::path::PathBuf;
+
+use super::{Cli, CliError, Setup, batch, count_character, io, tokens::expansion};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, CharacterCounter, CharacterFrequency, Codebook, Header, Smoothing};
+
+#[derive(clap::Args)]
+pub struct TrainArgs {
+    #[arg(required = true, value_name = "FILE")]
+    /// Files to count symbols in, directories standing for every file under them
+    files: Vec<PathBuf>,
+
+    #[arg(long, value_name = "METHOD", default_value = "mend")]
+    /// How rare symbols get a frequency: `mend` shares 5% among symbols never seen,
+    /// `laplace` adds one to every count and `add:<alpha>` adds alpha
+    smoothing: Smoothing,
+}
+
+pub fn run(cli: &Cli, setup: &Setup, args: &TrainArgs) -> Result<(), CliError> {
+    with_width!(cli.bits.unwrap_or(8), B => train::<B>(cli, setup, args))
+}
+
+fn train<B>(cli: &Cli, setup: &Setup, args: &TrainArgs) -> Result<(), CliError>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut files = Vec::new();
+    for path in &args.files {
+        if path.is_dir() {
+            batch::walk(path, None, &mut files)
+                .map_err(io(format!("list directory {:?}", path)))?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut counter = CharacterCounter::<B>::empty();
+    let mut bytes = 0;
+    for path in &files {
+        let f = File::open(path).map_err(io(format!("open file {:?}", path)))?;
+        bytes += count_character(&mut BufReader::new(f), &mut counter)
+            .map_err(io(format!("read file {:?}", path)))?;
+    }
+    if counter.total() == 0 {
+        return Err(CliError::Usage(
+            "there is nothing to train on, files are empty".to_string(),
+        ));
+    }
+
+    // Shuffling only renames symbols, so statistics are the same either way
+    let counter = setup.shuffle_counter(counter);
+    let freq = counter.finish_with(args.smoothing);
+    let encoding = setup
+        .new_encoding(&freq)
+        .with_normalization(cli.normalization());
+    let codebook = match args.smoothing {
+        Smoothing::Mend => Codebook::Counts(counter.counts()),
+        // Readers rebuild encodings from counts without smoothing them this way
+        Smoothing::Additive(_) => Codebook::Full,
+    };
+
+    let seen = counter.counts().iter().filter(|&&n| n > 0).count();
+    let entropy: f32 = counter
+        .counts()
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| n as f32 / counter.total() as f32)
+        .map(|p| -p * p.log2())
+        .sum();
+    let uniform = setup.new_encoding(&CharacterFrequency::<B>::all_equal());
+    cli.note(format_args!(
+        "read {} files, {} bytes, {} symbols of {} bits",
+        files.len(),
+        bytes,
+        counter.total(),
+        B::N
+    ));
+    cli.note(format_args!(
+        "symbols seen: {} of {}",
+        seen,
+        counter.counts().len()
+    ));
+    cli.note(format_args!("entropy: {:.4} bits per symbol", entropy));
+    cli.note(format_args!(
+        "expansion: {:.3} bytes per input byte, {:.3} with a uniform encoding",
+        expansion(&encoding, &freq),
+        expansion(&uniform, &freq)
+    ));
+
+    let header = Header::new(encoding).with_codebook(codebook);
+    let mut output: Box<dyn Write> = match &cli.output_file {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).map_err(io(format!("create file {:?}", path)))?,
+        )),
+        None => Box::new(stdout()),
+    };
+    header
+        .write(&mut output, cli.pretty_encoding)
+        .and_then(|()| output.flush())
+        .map_err(io("write encoding"))
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::bits::{Bits6, Bits8};
+
+    #[test]
+    fn test_train() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        std::fs::create_dir(path("corpus")).unwrap();
+        std::fs::write(path("corpus/a"), "aaaa").unwrap();
+        std::fs::write(path("corpus/b"), "ab").unwrap();
+        std::fs::write(path("c"), "c").unwrap();
+
+        let train = |args: &[&str]| {
+            let mut argv = vec!["hajiman", "-q", "train", "-o"];
+            let output = path("enc.json");
+            argv.push(&output);
+            argv.extend_from_slice(args);
+            super::super::run(Cli::try_parse_from(argv).unwrap())
+        };
+        train(&[&path("corpus"), &path("c")]).unwrap();
+        let header = Header::<Bits8>::read(BufReader::new(File::open(path("enc.json")).unwrap()))
+            .unwrap()
+            .0
+            .unwrap();
+        let Codebook::Counts(counts) = header.codebook else {
+            panic!("expected counts, found {:?}", header.codebook);
+        };
+        assert_eq!(counts[b'a' as usize], 5);
+        assert_eq!(counts[b'c' as usize], 1);
+        assert_eq!(counts.iter().sum::<usize>(), 7);
+
+        train(&["--bits", "6", "--smoothing", "laplace", &path("c")]).unwrap();
+        let header = Header::<Bits6>::read(BufReader::new(File::open(path("enc.json")).unwrap()))
+            .unwrap()
+            .0
+            .unwrap();
+        assert_eq!(header.codebook, Codebook::Full);
+
+        std::fs::write(path("empty"), "").unwrap();
+        assert_eq!(train(&[&path("empty")]).unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn test_train_skewed_frequency() {
+        // Frequencies this skewed used to make building the encoding panic
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        std::fs::write(path("zeros"), [0; 3000]).unwrap();
+
+        let (output, zeros) = (path("enc.json"), path("zeros"));
+        let argv = [
+            "hajiman",
+            "-q",
+            "--bits",
+            "6",
+            "--token-set",
+            "emoji",
+            "train",
+        ];
+        let argv = [&argv[..], &["-o", &output, &zeros]].concat();
+        super::super::run(Cli::try_parse_from(argv).unwrap()).unwrap();
+        let header = Header::<Bits6>::read(BufReader::new(File::open(&output).unwrap()))
+            .unwrap()
+            .0
+            .unwrap();
+        assert_eq!(header.encoding.encoding().validate(), Ok(()));
+    }
+}
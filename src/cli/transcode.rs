@@ -0,0 +1,175 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::{
+    Cli, CliError, Input, Setup, Source, finish_header, io, key_flag, read_encoding_file,
+    read_source, source_header,
+};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, EncodeWriter, Flag, Header, RawHeader};
+
+#[derive(clap::Args)]
+pub struct TranscodeArgs {
+    /// Input from command line argument intead of standard input
+    data: Option<String>,
+
+    #[arg(long, value_name = "ENCODING_FILE")]
+    /// Encoding the input is encoded with, instead of that of its header or
+    /// `--encoding-file`
+    from: Option<PathBuf>,
+
+    #[arg(long, value_name = "ENCODING_FILE")]
+    /// Encoding to encode the output with, of any symbol width
+    to: PathBuf,
+}
+
+impl TranscodeArgs {
+    pub fn data(&self) -> &Option<String> {
+        &self.data
+    }
+}
+
+pub fn run(
+    cli: &Cli,
+    setup: &Setup,
+    args: &TranscodeArgs,
+    mut input: Input,
+    output: &mut dyn Write,
+) -> Result<(), CliError> {
+    let from = match &args.from {
+        Some(path) => Some(read_encoding_file(path)?),
+        None => setup.file_header.clone(),
+    };
+    let to = read_encoding_file(&args.to)?;
+    let source = read_source(cli, setup, from, &mut input)?;
+    with_width!(source.bits, S => with_width!(to.bits(), T => {
+        transcode::<S, T>(cli, setup, input, source, to, output)
+    }))
+}
+
+/// Decode `input` as `source` tells with `S` symbols, and encode it again with the
+/// `to` encoding of `T` symbols
+fn transcode<S, T>(
+    cli: &Cli,
+    setup: &Setup,
+    mut input: Input,
+    source: Source,
+    to: RawHeader,
+    output: &mut dyn Write,
+) -> Result<(), CliError>
+where
+    S: Bits + serde::Serialize + serde::de::DeserializeOwned,
+    T: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let key = setup.key.as_ref();
+    let header = source_header::<S>(cli, setup, source)?;
+    if header.fec.is_some() {
+        return Err(CliError::Usage(
+            "honey water with FEC cannot be transcoded, decode it first".to_string(),
+        ));
+    }
+    let checked = header.flags.contains(&Flag::Crc32) || cli.require_checksum;
+    let decoder = header
+        .keyed_encoding(key)
+        .map_err(CliError::Usage)?
+        .decoder()
+        .map_err(|e| CliError::MalformedHeader(format!("tokens cannot be told apart: {:?}", e)))?;
+
+    // The payload is passed on as it is, still compressed or encrypted, so the header
+    // keeps telling how
+    let to = to
+        .into_header::<T>()
+        .map_err(|e| CliError::from(e).context("target encoding"))?;
+    let mut target = Header::new(to.encoding)
+        .with_codebook(to.codebook)
+        .with_length(header.length)
+        .with_encryption(header.encryption.clone())
+        .with_compression(header.compression);
+    if checked || cli.checksum {
+        target.flags.push(Flag::Crc32);
+    }
+    target.flags.extend(key_flag(cli, setup));
+    let target = finish_header(cli, target);
+    let encoder = target
+        .keyed_encoding(key)
+        .map_err(CliError::Usage)?
+        .encoder();
+
+    target
+        .write(&mut *output, cli.pretty_encoding)
+        .map_err(io("write header to output"))?;
+    let writer = EncodeWriter::new(&encoder, &mut *output, target.flags.contains(&Flag::Crc32));
+    let (decoded, _) = crate::transcode(input.reader(), &decoder, checked, writer, header.length)?;
+    cli.detail(format_args!(
+        "transcoded {} bytes from {}-bit to {}-bit symbols",
+        decoded,
+        S::N,
+        T::N
+    ));
+    match header.length {
+        Some(length) if decoded < length => Err(CliError::Decode(format!(
+            "honey water ends early: decoded {} of {} bytes",
+            decoded, length
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+    use crate::bits::Bits4;
+
+    #[test]
+    fn test_transcode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        let data = "哈基米 honey water, not a whole group";
+        std::fs::write(path("in"), data).unwrap();
+
+        let hajiman = |args: &[&str]| {
+            // Keys apply to both encodings
+            let mut argv = vec!["hajiman", "-q", "--key", "k"];
+            argv.extend_from_slice(args);
+            super::super::run(Cli::try_parse_from(argv).unwrap())
+        };
+        let input = path("in");
+        for (bits, set) in [("4", "emoji"), ("6", "hajimi"), ("8", "morse")] {
+            let encoding = path(&format!("e{}", bits));
+            hajiman(&[
+                "--bits",
+                bits,
+                "--token-set",
+                set,
+                "-o",
+                &encoding,
+                "train",
+                &input,
+            ])
+            .unwrap();
+        }
+
+        let (e4, e6, e8) = (path("e4"), path("e6"), path("e8"));
+        let (a, b, c, out) = (path("a"), path("b"), path("c"), path("out"));
+        hajiman(&["-i", &input, "-e", &e6, "--checksum", "-o", &a, "encode"]).unwrap();
+        hajiman(&["-i", &a, "-o", &b, "transcode", "--to", &e8]).unwrap();
+        hajiman(&["-i", &b, "-o", &c, "transcode", "--to", &e4]).unwrap();
+        hajiman(&["-i", &c, "-o", &out, "decode"]).unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), data);
+        let f = std::io::BufReader::new(std::fs::File::open(&c).unwrap());
+        let header = RawHeader::read(f).unwrap().0.unwrap();
+        let header = header.into_header::<Bits4>().unwrap();
+        assert_eq!(header.length, Some(data.len() as u64));
+        assert!(header.flags.contains(&Flag::Crc32));
+
+        // Input encoded with another encoding than that of its header
+        let result = hajiman(&["-i", &a, "-o", &b, "transcode", "--from", &e8, "--to", &e4]);
+        assert!(matches!(result, Err(CliError::MalformedHeader(_))));
+
+        hajiman(&["-i", &input, "--fec", "4+2", "-o", &a, "encode"]).unwrap();
+        let result = hajiman(&["-i", &a, "-o", &b, "transcode", "--to", &e6]);
+        assert!(matches!(result, Err(CliError::Usage(_))));
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{Cursor, Write, stdout};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::{Cli, CliError, Input, Setup, decode, decode_input, encode_input, io, read_source};
+use crate::dyn_jimi::with_width;
+use crate::{Bits, Flag, Header, Key, TokenSet, WIDTHS};
+
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// File to encode and decode back
+    file: PathBuf,
+}
+
+/// Outcome of encoding the file with one token set and width, and decoding it back
+struct Roundtrip {
+    tokens: String,
+    bits: u32,
+    encoded_len: usize,
+    encode_time: Duration,
+    decode_time: Duration,
+    /// Zero bytes decoded past the end of the payload when its length is not given,
+    /// unless it is split into FEC shards
+    padding: Option<usize>,
+    /// Whether padding is only zeros, less than a group of symbols, and dropped when
+    /// the length is given
+    padding_ok: bool,
+    /// Why the roundtrip failed, if it did
+    error: Option<String>,
+}
+
+pub fn run(cli: &Cli, setup: &Setup, args: &VerifyArgs) -> Result<(), CliError> {
+    let data = std::fs::read(&args.file).map_err(io(format!("read file {:?}", args.file)))?;
+    // A missing passphrase would fail every roundtrip alike
+    if cli.encrypt {
+        cli.passphrase()?;
+    }
+
+    // Token sets and widths given in arguments narrow down what is tried
+    let token_sets: Vec<Option<&'static TokenSet>> = match (&setup.custom_tokens, &cli.token_set) {
+        (Some(_), _) => vec![None],
+        (None, Some(_)) => vec![Some(setup.token_set)],
+        (None, None) => TokenSet::all().iter().map(Some).collect(),
+    };
+    let widths = match cli.bits {
+        Some(bits) => vec![bits],
+        None => WIDTHS.to_vec(),
+    };
+
+    let mut roundtrips = Vec::new();
+    for &token_set in &token_sets {
+        for &bits in &widths {
+            roundtrips.push(with_width!(bits, B => {
+                Ok::<_, CliError>(roundtrip::<B>(cli, setup, token_set, &data))
+            })?);
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:<14} {:>4} {:>9} {:>10} {:>10} {:>7}  result",
+        "tokens", "bits", "expansion", "encode ms", "decode ms", "padding"
+    )
+    .unwrap();
+    for roundtrip in &roundtrips {
+        writeln!(
+            out,
+            "{:<14} {:>4} {:>9.3} {:>10.2} {:>10.2} {:>7}  {}",
+            roundtrip.tokens,
+            roundtrip.bits,
+            roundtrip.encoded_len as f32 / data.len().max(1) as f32,
+            roundtrip.encode_time.as_secs_f64() * 1000.0,
+            roundtrip.decode_time.as_secs_f64() * 1000.0,
+            format!(
+                "{} {}",
+                roundtrip.padding.map_or("-".to_string(), |n| n.to_string()),
+                if roundtrip.padding_ok { "ok" } else { "bad" }
+            ),
+            roundtrip.error.as_deref().unwrap_or("ok")
+        )
+        .unwrap();
+    }
+
+    let mut output: Box<dyn Write> = match &cli.output_file {
+        Some(path) => Box::new(File::create(path).map_err(io(format!("create file {:?}", path)))?),
+        None => Box::new(stdout()),
+    };
+    output
+        .write_all(out.as_bytes())
+        .and_then(|()| output.flush())
+        .map_err(io("write output"))?;
+
+    let failed = roundtrips
+        .iter()
+        .filter(|roundtrip| roundtrip.error.is_some() || !roundtrip.padding_ok)
+        .count();
+    cli.note(format_args!(
+        "{} of {} roundtrips of {} bytes passed",
+        roundtrips.len() - failed,
+        roundtrips.len(),
+        data.len()
+    ));
+    match failed {
+        0 => Ok(()),
+        n => Err(CliError::Decode(format!(
+            "{} of {} roundtrips failed",
+            n,
+            roundtrips.len()
+        ))),
+    }
+}
+
+impl Roundtrip {
+    fn new(token_set: Option<&'static TokenSet>, bits: u32) -> Self {
+        Self {
+            tokens: token_set.map_or("custom", TokenSet::name).to_string(),
+            bits,
+            encoded_len: 0,
+            encode_time: Duration::ZERO,
+            decode_time: Duration::ZERO,
+            padding: None,
+            padding_ok: false,
+            error: None,
+        }
+    }
+}
+
+/// Encode `data` with the tokens of `token_set`, or the custom ones without one, and
+/// decode it back, the way `encode` and `decode` do with the options given in arguments
+fn roundtrip<B>(
+    cli: &Cli,
+    setup: &Setup,
+    token_set: Option<&'static TokenSet>,
+    data: &[u8],
+) -> Roundtrip
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let mut roundtrip = Roundtrip::new(token_set, B::N);
+    let setup = Setup {
+        custom_tokens: match token_set {
+            Some(_) => None,
+            None => setup.custom_tokens.clone(),
+        },
+        token_set: token_set.unwrap_or(setup.token_set),
+        key: setup.key.clone(),
+        file_header: None,
+    };
+
+    let start = Instant::now();
+    let mut encoded = Vec::new();
+    let input = Input::Seekable(Box::new(Cursor::new(data.to_vec())));
+    let result = encode_input::<B>(cli, &setup, input, &mut encoded);
+    roundtrip.encode_time = start.elapsed();
+    if let Err(e) = result {
+        roundtrip.error = Some(format!("encode failed: {}", e));
+        return roundtrip;
+    }
+    let mut body = Cursor::new(&encoded[..]);
+    let header = match Header::<B>::read(&mut body) {
+        Ok((Some(header), _)) => header,
+        Ok((None, _)) => {
+            roundtrip.error = Some("encoded without a header".to_string());
+            return roundtrip;
+        }
+        Err(e) => {
+            roundtrip.error = Some(format!("encoded with a malformed header: {}", e));
+            return roundtrip;
+        }
+    };
+    let body = &encoded[body.position() as usize..];
+    roundtrip.encoded_len = body.len();
+
+    let start = Instant::now();
+    let mut decoded = Vec::new();
+    let mut input = Input::Seekable(Box::new(Cursor::new(encoded.clone())));
+    let result = read_source(cli, &setup, None, &mut input)
+        .and_then(|source| decode_input::<B>(cli, &setup, input, source, &mut decoded));
+    roundtrip.decode_time = start.elapsed();
+    roundtrip.error = match result {
+        Err(e) => Some(format!("decode failed: {}", e)),
+        Ok(()) => mismatch(data, &decoded),
+    };
+
+    // Each line of FEC shards is padded, and cut to the shard length it records
+    if header.fec.is_some() {
+        roundtrip.padding_ok = true;
+    } else if let Some((padding, ok)) = padding(&header, setup.key.as_ref(), body) {
+        roundtrip.padding = Some(padding);
+        roundtrip.padding_ok = ok;
+    }
+    roundtrip
+}
+
+/// Bytes decoded from `body` past the payload when the length in `header` is not
+/// given, and whether they are only zeros padding the last group of symbols
+fn padding<B>(header: &Header<B>, key: Option<&Key>, body: &[u8]) -> Option<(usize, bool)>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let decoder = header.keyed_encoding(key).ok()?.decoder().ok()?;
+    let checked = header.flags.contains(&Flag::Crc32);
+    let mut payload = Vec::new();
+    decode(
+        &mut Cursor::new(body),
+        &decoder,
+        &mut payload,
+        header.length,
+        checked,
+    )
+    .ok()?;
+    let mut padded = Vec::new();
+    decode(&mut Cursor::new(body), &decoder, &mut padded, None, checked).ok()?;
+
+    let group_bytes = B::group_bytes();
+    let padding = padded.len().saturating_sub(payload.len());
+    let ok = padded.starts_with(&payload)
+        && padded[payload.len()..].iter().all(|&b| b == 0)
+        && padding < group_bytes
+        && (payload.len() + padding).is_multiple_of(group_bytes);
+    Some((padding, ok))
+}
+
+/// Where `decoded` first differs from `data`, if it does
+fn mismatch(data: &[u8], decoded: &[u8]) -> Option<String> {
+    match data.iter().zip(decoded).position(|(a, b)| a != b) {
+        Some(i) => Some(format!(
+            "byte {} differs: {:#04x} became {:#04x}",
+            i, data[i], decoded[i]
+        )),
+        None if data.len() != decoded.len() => Some(format!(
+            "decoded {} bytes instead of {}",
+            decoded.len(),
+            data.len()
+        )),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |p: &str| dir.path().join(p).to_str().unwrap().to_string();
+        std::fs::write(path("in"), "哈基米 honey water, not a whole group").unwrap();
+        std::fs::write(path("zeros"), [0; 3000]).unwrap();
+
+        let (input, zeros, report) = (path("in"), path("zeros"), path("report"));
+        let verify = |args: &[&str], input: &str| {
+            let mut argv = vec!["hajiman", "-q", "-o", &report];
+            argv.extend_from_slice(args);
+            argv.extend_from_slice(&["verify", input]);
+            super::super::run(Cli::try_parse_from(argv).unwrap())?;
+            Ok::<_, CliError>(std::fs::read_to_string(&report).unwrap())
+        };
+        let report = verify(&[], &input).unwrap();
+        assert_eq!(
+            report.lines().count(),
+            1 + TokenSet::all().len() * WIDTHS.len()
+        );
+        assert!(report.lines().skip(1).all(|line| line.ends_with("ok  ok")));
+
+        let report = verify(&["-f", "--checksum", "--key", "k", "--bits", "6"], &input).unwrap();
+        assert_eq!(report.lines().count(), 1 + TokenSet::all().len());
+
+        let args = ["-f", "--compress", "zstd", "--fec", "4+2", "--bits", "4"];
+        let report = verify(&args, &zeros).unwrap();
+        let rows: Vec<&str> = report.lines().skip(1).collect();
+        assert_eq!(rows.len(), TokenSet::all().len());
+        for row in rows {
+            assert!(row.ends_with("- ok  ok"), "{row}");
+            // Zeros compress to almost nothing, even with parity
+            let expansion: f32 = row.split_whitespace().nth(2).unwrap().parse().unwrap();
+            assert!(expansion < 5.0, "{row}");
+        }
+
+        // Deriving keys is slow, so one roundtrip is enough
+        let args = ["--encrypt", "--passphrase", "p", "--compress", "auto"];
+        let narrow = ["--token-set", "meme", "--bits", "6"];
+        let report = verify(&[&args[..], &narrow].concat(), &input).unwrap();
+        assert_eq!(report.lines().count(), 2);
+        assert!(report.lines().nth(1).unwrap().ends_with("ok  ok"));
+        assert!(matches!(
+            verify(&["--encrypt"], &input),
+            Err(CliError::Usage(_))
+        ));
+
+        // Frequencies this skewed used to give overlapping codes
+        let report = verify(&["-f"], &zeros).unwrap();
+        assert!(report.lines().skip(1).all(|line| line.ends_with("ok  ok")));
+
+        assert_eq!(
+            mismatch(b"abc", b"abd").as_deref(),
+            Some("byte 2 differs: 0x63 became 0x64")
+        );
+        assert!(mismatch(b"abc", b"ab").is_some());
+    }
+}
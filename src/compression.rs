@@ -0,0 +1,135 @@
+use std::io::{Read, Write};
+
+/// Compression applied to the payload before it is encoded into honey water.
+///
+/// Encodings built from symbol frequency only make use of how often each byte
+/// appears, while these also make use of repeated strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Raw deflate, LZ77 followed by Huffman coding
+    Deflate,
+    /// Zstandard frame
+    Zstd,
+}
+
+#[derive(Debug)]
+pub struct DecompressError(String);
+
+impl std::fmt::Display for DecompressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "decompress payload failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for DecompressError {}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::Deflate, Compression::Zstd];
+
+    /// Most bytes a payload decompresses to when no smaller limit is known, so that a
+    /// small crafted payload cannot fill memory
+    pub const MAX_DECOMPRESSED: u64 = 1 << 30;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.finish())
+                    .expect("writing to a vector does not fail")
+            }
+            Compression::Zstd => {
+                ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest)
+            }
+        }
+    }
+
+    /// Decompress `data`, failing if it decompresses to more than `limit` bytes, or
+    /// [`Compression::MAX_DECOMPRESSED`] if not given
+    pub fn decompress(self, data: &[u8], limit: Option<u64>) -> Result<Vec<u8>, DecompressError> {
+        let limit = limit.unwrap_or(Self::MAX_DECOMPRESSED);
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            Compression::Zstd => Box::new(
+                ruzstd::decoding::StreamingDecoder::new(data)
+                    .map_err(|e| DecompressError(e.to_string()))?,
+            ),
+        };
+        // One byte past the limit tells a payload that is exactly as long from a
+        // longer one
+        let mut out = Vec::new();
+        reader
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut out)
+            .map_err(|e| DecompressError(e.to_string()))?;
+        if out.len() as u64 > limit {
+            return Err(DecompressError(format!(
+                "decompresses to more than {} bytes",
+                limit
+            )));
+        }
+        Ok(out)
+    }
+
+    /// The compression making `data` smallest as measured by `size`, or `None` if
+    /// none makes it smaller than it already is
+    pub fn select(data: &[u8], size: impl Fn(&[u8]) -> usize) -> Option<Compression> {
+        let uncompressed = size(data);
+        Self::ALL
+            .into_iter()
+            .map(|compression| (size(&compression.compress(data)), compression))
+            .filter(|&(compressed, _)| compressed < uncompressed)
+            .min_by_key(|&(compressed, _)| compressed)
+            .map(|(_, compression)| compression)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let text = "哈基米曼波 honey water, ".repeat(100);
+        for compression in Compression::ALL {
+            let compressed = compression.compress(text.as_bytes());
+            assert!(compressed.len() < text.len() / 4, "{:?}", compression);
+            assert_eq!(
+                compression.decompress(&compressed, None).unwrap(),
+                text.as_bytes()
+            );
+            assert!(compression.decompress(b"not compressed", None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let zeros = vec![0; 1 << 16];
+        for compression in Compression::ALL {
+            let compressed = compression.compress(&zeros);
+            let limit = Some(zeros.len() as u64);
+            assert_eq!(compression.decompress(&compressed, limit).unwrap(), zeros);
+            let limit = Some(zeros.len() as u64 - 1);
+            assert!(compression.decompress(&compressed, limit).is_err());
+        }
+    }
+
+    #[test]
+    fn test_select_compression() {
+        let text = "abcabcabc".repeat(50);
+        assert!(Compression::select(text.as_bytes(), <[u8]>::len).is_some());
+
+        let noise: Vec<u8> = (0..64u32).map(|i| (i * 97 % 256) as u8).collect();
+        assert_eq!(Compression::select(&noise, <[u8]>::len), None);
+    }
+}
@@ -0,0 +1,706 @@
+use std::io::{BufRead, Read, Write};
+
+use crate::bits_key::Bits;
+use crate::characters::{CharacterCounter, CharacterFrequency};
+use crate::compression::Compression;
+use crate::crypto::Encryption;
+use crate::encoding::Encoding;
+use crate::fec::Redundancy;
+use crate::jimi::{JimiEncoding, serialized::Tokens};
+use crate::key::Key;
+use crate::lexing::normalize::Normalization;
+
+/// Start of every header, followed by the format version
+pub const MAGIC: &str = "hajiman/";
+/// Version of the header format written by [`Header::write`]
+pub const VERSION: u32 = 2;
+/// Headers longer than this are rejected instead of being read into memory
+const MAX_HEADER_LEN: usize = 1 << 24;
+
+/// How the codebook is written in a header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Codebook {
+    /// Code of every symbol
+    Full,
+    /// Nothing, as the encoding was built assuming uniform symbol frequency
+    Uniform,
+    /// Symbol counts the encoding was built from, so the reader can build it again
+    Counts(Vec<usize>),
+}
+
+/// Optional features the honey water following a header uses.
+///
+/// Readers reject flags they do not know, since they cannot decode such data correctly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flag {
+    /// Honey water ends with a CRC32 trailer, see [`crate::Checksum`]
+    Crc32,
+    /// Encoding is shuffled by a key which is not recorded, see [`crate::Key`]
+    Keyed,
+    /// Like [`Flag::Keyed`], with tokens of the same cost shuffled too
+    KeyedTokens,
+}
+
+/// Everything needed to decode the honey water following it.
+///
+/// Written as a line `hajiman/<version> <length> <crc32>`, then `length` bytes of
+/// JSON whose CRC32 is `crc32`, then a newline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header<B>
+where
+    B: Bits,
+{
+    pub encoding: JimiEncoding<B>,
+    pub codebook: Codebook,
+    /// Number of bytes encoded, if known when the header was written.
+    ///
+    /// With encryption, this counts the ciphertext.
+    pub length: Option<u64>,
+    pub flags: Vec<Flag>,
+    /// Redundancy of the Reed–Solomon stage, if the honey water has one
+    pub fec: Option<Redundancy>,
+    /// How the payload is encrypted, if it is
+    pub encryption: Option<Encryption>,
+    /// How the payload is compressed before being encrypted, if it is
+    pub compression: Option<Compression>,
+}
+
+#[derive(Debug)]
+pub enum ContainerError {
+    Io(std::io::Error),
+    UnsupportedVersion(String),
+    Malformed(String),
+    ChecksumMismatch { expected: u32, found: u32 },
+    WidthMismatch { expected: u32, found: u32 },
+}
+
+impl std::fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerError::Io(e) => write!(f, "read header failed: {}", e),
+            ContainerError::UnsupportedVersion(v) => {
+                write!(
+                    f,
+                    "unsupported header version {:?}, expected {}",
+                    v, VERSION
+                )
+            }
+            ContainerError::Malformed(e) => write!(f, "malformed header: {}", e),
+            ContainerError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "header is corrupted: checksum is {:08x}, expected {:08x}",
+                found, expected
+            ),
+            ContainerError::WidthMismatch { expected, found } => write!(
+                f,
+                "header is for {}-bit symbols, expected {}-bit",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+impl From<std::io::Error> for ContainerError {
+    fn from(value: std::io::Error) -> Self {
+        ContainerError::Io(value)
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct HeaderRepr<B>
+where
+    B: Bits,
+{
+    bits: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    length: Option<u64>,
+    tokens: Tokens,
+    #[serde(default, skip_serializing_if = "Normalization::is_none")]
+    normalization: Normalization,
+    codebook: CodebookRepr<B>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    flags: Vec<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fec: Option<Redundancy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<Compression>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum CodebookRepr<B>
+where
+    B: Bits,
+{
+    Full(Encoding<B>),
+    Uniform,
+    Counts(Vec<usize>),
+}
+
+impl<B> Header<B>
+where
+    B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Header writing the full codebook of `encoding`, without length or flags
+    pub fn new(encoding: JimiEncoding<B>) -> Self {
+        Self {
+            encoding,
+            codebook: Codebook::Full,
+            length: None,
+            flags: Vec::new(),
+            fec: None,
+            encryption: None,
+            compression: None,
+        }
+    }
+
+    /// Write the codebook as `codebook`, which must describe how the encoding was built
+    pub fn with_codebook(self, codebook: Codebook) -> Self {
+        Self { codebook, ..self }
+    }
+
+    pub fn with_length(self, length: Option<u64>) -> Self {
+        Self { length, ..self }
+    }
+
+    pub fn with_fec(self, fec: Option<Redundancy>) -> Self {
+        Self { fec, ..self }
+    }
+
+    pub fn with_encryption(self, encryption: Option<Encryption>) -> Self {
+        Self { encryption, ..self }
+    }
+
+    pub fn with_compression(self, compression: Option<Compression>) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    /// Whether the honey water is encoded with a key, and whether it shuffles tokens
+    pub fn keyed(&self) -> Option<bool> {
+        if self.flags.contains(&Flag::KeyedTokens) {
+            Some(true)
+        } else if self.flags.contains(&Flag::Keyed) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The encoding honey water is actually encoded with, shuffled by `key` if the
+    /// header says so
+    pub fn keyed_encoding(&self, key: Option<&Key>) -> Result<JimiEncoding<B>, String> {
+        match (self.keyed(), key) {
+            (Some(shuffle_tokens), Some(key)) => Ok(self.encoding.keyed(key, shuffle_tokens)),
+            (Some(_), None) => {
+                Err("honey water is encoded with a key, which is missing".to_string())
+            }
+            (None, Some(_)) => {
+                Err("a key is given, but honey water is not encoded with one".to_string())
+            }
+            (None, None) => Ok(self.encoding.clone()),
+        }
+    }
+
+    pub fn write(&self, mut writer: impl Write, pretty: bool) -> std::io::Result<()> {
+        let repr = self.to_repr();
+        let json = if pretty {
+            serde_json::to_vec_pretty(&repr)?
+        } else {
+            serde_json::to_vec(&repr)?
+        };
+
+        writeln!(
+            writer,
+            "{}{} {} {:08x}",
+            MAGIC,
+            VERSION,
+            json.len(),
+            crc32fast::hash(&json)
+        )?;
+        writer.write_all(&json)?;
+        writeln!(writer)
+    }
+
+    /// Read the header at the start of `reader`, leaving it right before the honey water.
+    ///
+    /// Returns `None` if there is no header, with the bytes consumed looking for one,
+    /// which are the start of the honey water. Encodings written as bare JSON by earlier
+    /// versions are read as headers with a full codebook.
+    pub fn read(reader: impl BufRead) -> Result<(Option<Self>, Vec<u8>), ContainerError> {
+        let (raw, consumed) = RawHeader::read(reader)?;
+        Ok((raw.map(RawHeader::into_header).transpose()?, consumed))
+    }
+
+    /// This header, forgetting its symbol width till it is read back
+    pub fn to_raw(&self) -> RawHeader {
+        RawHeader {
+            bits: B::N,
+            json: serde_json::to_value(self.to_repr()).expect("header serializes"),
+            legacy: false,
+        }
+    }
+
+    fn to_repr(&self) -> HeaderRepr<B> {
+        HeaderRepr {
+            bits: B::N,
+            length: self.length,
+            tokens: Tokens::new(self.encoding.tokens(), self.encoding.token_set()),
+            normalization: *self.encoding.normalization(),
+            codebook: match &self.codebook {
+                Codebook::Full => CodebookRepr::Full(self.encoding.encoding().clone()),
+                Codebook::Uniform => CodebookRepr::Uniform,
+                Codebook::Counts(counts) => CodebookRepr::Counts(counts.clone()),
+            },
+            flags: self.flags.clone(),
+            fec: self.fec,
+            encryption: self.encryption.clone(),
+            compression: self.compression,
+        }
+    }
+
+    fn from_repr(repr: HeaderRepr<B>) -> Result<Self, String> {
+        if let Some(encryption) = &repr.encryption {
+            encryption.kdf.check_costs()?;
+        }
+        if let Some(fec) = &repr.fec {
+            fec.validate()
+                .map_err(|e| format!("invalid redundancy: {}", e))?;
+        }
+        let (tokens, token_set) = repr.tokens.resolve()?;
+        if tokens.len() < 2 {
+            return Err(format!(
+                "at least two tokens are needed, found {}",
+                tokens.len()
+            ));
+        }
+        if tokens.iter().flatten().any(|s| s.is_empty()) {
+            return Err("empty token".to_string());
+        }
+
+        let build = |freq: &CharacterFrequency<B>| match token_set {
+            Some(set) => JimiEncoding::from_token_set(set, freq),
+            None => JimiEncoding::with_aliases(tokens.clone(), freq),
+        };
+        let encoding = match &repr.codebook {
+            CodebookRepr::Full(encoding) => {
+                encoding.validate()?;
+                if encoding.n_letters().index() != tokens.len() {
+                    return Err(format!(
+                        "codebook uses {} letters, but there are {} tokens",
+                        encoding.n_letters().index(),
+                        tokens.len()
+                    ));
+                }
+                JimiEncoding::from_parts(encoding.clone(), tokens.clone(), token_set)
+            }
+            CodebookRepr::Uniform => build(&CharacterFrequency::all_equal()),
+            CodebookRepr::Counts(counts) => {
+                let counter = CharacterCounter::<B>::from_counts(counts).ok_or_else(|| {
+                    format!("expected {} symbol counts, not all zero", 2usize.pow(B::N))
+                })?;
+                build(&counter.finish())
+            }
+        };
+
+        Ok(Self {
+            encoding: encoding.with_normalization(repr.normalization),
+            codebook: match repr.codebook {
+                CodebookRepr::Full(_) => Codebook::Full,
+                CodebookRepr::Uniform => Codebook::Uniform,
+                CodebookRepr::Counts(counts) => Codebook::Counts(counts),
+            },
+            length: repr.length,
+            flags: repr.flags,
+            fec: repr.fec,
+            encryption: repr.encryption,
+            compression: repr.compression,
+        })
+    }
+}
+
+/// Header as read, before the symbol width it records is known.
+///
+/// Turned into a [`Header`] of that width by [`RawHeader::into_header`], which is how
+/// readers taking any width dispatch on it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawHeader {
+    bits: u32,
+    json: serde_json::Value,
+    /// Bare JSON encoding written by earlier versions, which only had 8-bit symbols
+    legacy: bool,
+}
+
+impl RawHeader {
+    /// Like [`Header::read`], for any symbol width.
+    ///
+    /// Nothing is consumed from input without a header, unless short reads leave it
+    /// looking like the start of the magic for a while.
+    pub fn read(mut reader: impl BufRead) -> Result<(Option<Self>, Vec<u8>), ContainerError> {
+        // Reads from pipes can be shorter than the magic, so what matches it so far is
+        // consumed and more read until the magic is whole or does not match
+        let mut matched = 0;
+        while matched < MAGIC.len() {
+            let buf = reader.fill_buf()?;
+            if matched == 0 && buf.starts_with(b"{") {
+                return Ok((Some(Self::read_legacy(reader)?), Vec::new()));
+            }
+            let n = buf.len().min(MAGIC.len() - matched);
+            if n == 0 || buf[..n] != MAGIC.as_bytes()[matched..matched + n] {
+                return Ok((None, MAGIC.as_bytes()[..matched].to_vec()));
+            }
+            reader.consume(n);
+            matched += n;
+        }
+        Ok((Some(Self::read_current(reader)?), Vec::new()))
+    }
+
+    /// Width of symbols in bits
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The header, which must be of `B` symbols
+    pub fn into_header<B>(self) -> Result<Header<B>, ContainerError>
+    where
+        B: Bits + serde::Serialize + serde::de::DeserializeOwned,
+    {
+        if self.bits != B::N {
+            return Err(ContainerError::WidthMismatch {
+                expected: B::N,
+                found: self.bits,
+            });
+        }
+        let malformed = |e: serde_json::Error| ContainerError::Malformed(e.to_string());
+        if self.legacy {
+            return Ok(Header::new(
+                serde_json::from_value(self.json).map_err(malformed)?,
+            ));
+        }
+        let repr: HeaderRepr<B> = serde_json::from_value(self.json).map_err(malformed)?;
+        Header::from_repr(repr).map_err(ContainerError::Malformed)
+    }
+
+    fn read_legacy(mut reader: impl BufRead) -> Result<Self, ContainerError> {
+        let json = serde_json::Deserializer::from_reader(&mut reader)
+            .into_iter::<serde_json::Value>()
+            .next()
+            .ok_or_else(|| ContainerError::Malformed("no encoding found".to_string()))?
+            .map_err(|e| ContainerError::Malformed(e.to_string()))?;
+        reader.skip_until(b'\n')?;
+        Ok(Self {
+            bits: 8,
+            json,
+            legacy: true,
+        })
+    }
+
+    /// Read the rest of a header whose magic is consumed
+    fn read_current(mut reader: impl BufRead) -> Result<Self, ContainerError> {
+        let mut line = Vec::new();
+        reader
+            .by_ref()
+            .take((64 - MAGIC.len()) as u64)
+            .read_until(b'\n', &mut line)?;
+        let line = std::str::from_utf8(&line)
+            .ok()
+            .and_then(|line| line.strip_suffix('\n'))
+            .ok_or_else(|| ContainerError::Malformed("first line is cut off".to_string()))?;
+
+        let mut fields = line.split(' ');
+        let version = fields.next().unwrap_or_default();
+        if version.parse::<u32>().ok() != Some(VERSION) {
+            return Err(ContainerError::UnsupportedVersion(version.to_string()));
+        }
+        let (Some(len), Some(checksum), None) = (
+            fields.next().and_then(|s| s.parse::<usize>().ok()),
+            fields.next().and_then(|s| u32::from_str_radix(s, 16).ok()),
+            fields.next(),
+        ) else {
+            return Err(ContainerError::Malformed(format!(
+                "expected length and checksum in {:?}",
+                line
+            )));
+        };
+        if len > MAX_HEADER_LEN {
+            return Err(ContainerError::Malformed(format!(
+                "header of {} bytes is too long",
+                len
+            )));
+        }
+
+        let mut json = vec![0; len + 1];
+        reader.read_exact(&mut json).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                ContainerError::Malformed("input ends inside header".to_string())
+            }
+            _ => ContainerError::Io(e),
+        })?;
+        if json.pop() != Some(b'\n') {
+            return Err(ContainerError::Malformed(
+                "header is not followed by a newline".to_string(),
+            ));
+        }
+
+        let found = crc32fast::hash(&json);
+        if found != checksum {
+            return Err(ContainerError::ChecksumMismatch {
+                expected: checksum,
+                found,
+            });
+        }
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&json).map_err(|e| ContainerError::Malformed(e.to_string()))?;
+        let bits = json
+            .get("bits")
+            .and_then(|bits| bits.as_u64())
+            .ok_or_else(|| ContainerError::Malformed("missing symbol width".to_string()))?;
+        Ok(Self {
+            bits: bits as u32,
+            json,
+            legacy: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor};
+
+    use super::*;
+    use crate::bits::{Bits6, Bits8};
+    use crate::lexing::normalize::NormalForm;
+    use crate::{Kdf, TokenSet, hajimi_tokens};
+
+    fn roundtrip(header: &Header<Bits8>, pretty: bool) {
+        let mut written = Vec::new();
+        header.write(&mut written, pretty).unwrap();
+        written.extend_from_slice("哈基米".as_bytes());
+
+        let mut reader = Cursor::new(&written);
+        let read = Header::<Bits8>::read(&mut reader).unwrap().0.unwrap();
+        assert_eq!(&read, header);
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "哈基米");
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let set = TokenSet::by_name("hajimi").unwrap();
+        let uniform = JimiEncoding::from_token_set(set, &CharacterFrequency::all_equal());
+        roundtrip(&Header::new(uniform.clone()), false);
+        roundtrip(
+            &Header::new(uniform.clone())
+                .with_codebook(Codebook::Uniform)
+                .with_length(Some(42))
+                .with_fec(Some(Redundancy::new(16, 4)))
+                .with_encryption(Some(Encryption::random().unwrap()))
+                .with_compression(Some(Compression::Zstd)),
+            true,
+        );
+
+        let mut counter = CharacterCounter::<Bits8>::empty();
+        counter.count(Bits8::iter_bytes(b"hello honey water").data);
+        let encoding = JimiEncoding::new(hajimi_tokens(), &counter.finish()).with_normalization(
+            Normalization {
+                form: Some(NormalForm::Nfkc),
+                case_fold: true,
+            },
+        );
+        roundtrip(
+            &Header::new(encoding).with_codebook(Codebook::Counts(counter.counts())),
+            false,
+        );
+    }
+
+    #[test]
+    fn test_read_hostile_redundancy() {
+        let set = TokenSet::by_name("hajimi").unwrap();
+        let uniform = JimiEncoding::<Bits8>::from_token_set(set, &CharacterFrequency::all_equal());
+        let read = |fec: Redundancy| {
+            let mut written = Vec::new();
+            Header::new(uniform.clone())
+                .with_codebook(Codebook::Uniform)
+                .with_fec(Some(fec))
+                .write(&mut written, false)
+                .unwrap();
+            Header::<Bits8>::read(Cursor::new(written))
+        };
+
+        assert!(read(Redundancy::new(16, 4)).is_ok());
+        let hostile = [
+            Redundancy::new(usize::MAX, 4),
+            Redundancy::new(0, 4),
+            Redundancy {
+                shard_len: usize::MAX - 4,
+                ..Redundancy::new(16, 4)
+            },
+        ];
+        for fec in hostile {
+            assert!(
+                matches!(read(fec), Err(ContainerError::Malformed(e)) if e.contains("redundancy")),
+                "{:?}",
+                fec
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_expensive_kdf() {
+        let set = TokenSet::by_name("hajimi").unwrap();
+        let uniform = JimiEncoding::<Bits8>::from_token_set(set, &CharacterFrequency::all_equal());
+        let read = |encryption: Encryption| {
+            let mut written = Vec::new();
+            Header::new(uniform.clone())
+                .with_codebook(Codebook::Uniform)
+                .with_encryption(Some(encryption))
+                .write(&mut written, false)
+                .unwrap();
+            Header::<Bits8>::read(Cursor::new(written))
+        };
+
+        let encryption = Encryption::random().unwrap();
+        assert!(read(encryption.clone()).is_ok());
+        let mut kdf = encryption.kdf.clone();
+        kdf.m_cost = Kdf::MAX_M_COST + 1;
+        let result = read(Encryption::new(kdf, encryption.nonce.clone()));
+        assert!(matches!(result, Err(ContainerError::Malformed(e)) if e.contains("m_cost")));
+        let mut kdf = encryption.kdf.clone();
+        kdf.t_cost = u32::MAX;
+        let result = read(Encryption::new(kdf, encryption.nonce.clone()));
+        assert!(matches!(result, Err(ContainerError::Malformed(e)) if e.contains("t_cost")));
+        let mut kdf = encryption.kdf;
+        kdf.p_cost = Kdf::MAX_P_COST + 1;
+        let result = read(Encryption::new(kdf, encryption.nonce));
+        assert!(matches!(result, Err(ContainerError::Malformed(e)) if e.contains("p_cost")));
+    }
+
+    #[test]
+    fn test_read_legacy_header() {
+        let encoding =
+            JimiEncoding::<Bits8>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let mut written = serde_json::to_vec_pretty(&encoding).unwrap();
+        written.extend_from_slice("\n哈基米".as_bytes());
+
+        let mut reader = Cursor::new(&written);
+        let read = Header::<Bits8>::read(&mut reader).unwrap().0.unwrap();
+        assert_eq!(read, Header::new(encoding));
+        assert_eq!(&written[reader.position() as usize..], "哈基米".as_bytes());
+    }
+
+    #[test]
+    fn test_read_raw_header() {
+        let encoding =
+            JimiEncoding::<Bits6>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let header = Header::new(encoding).with_length(Some(7));
+        let mut written = Vec::new();
+        header.write(&mut written, false).unwrap();
+
+        let raw = RawHeader::read(Cursor::new(&written)).unwrap().0.unwrap();
+        assert_eq!(raw.bits(), 6);
+        assert_eq!(raw, header.to_raw());
+        assert!(matches!(
+            raw.clone().into_header::<Bits8>(),
+            Err(ContainerError::WidthMismatch {
+                expected: 8,
+                found: 6
+            })
+        ));
+        assert_eq!(raw.into_header::<Bits6>().unwrap(), header);
+    }
+
+    #[test]
+    fn test_read_short_reads() {
+        let encoding =
+            JimiEncoding::<Bits6>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let header = Header::new(encoding).with_length(Some(7));
+        let mut written = Vec::new();
+        header.write(&mut written, false).unwrap();
+        written.extend_from_slice("哈基米".as_bytes());
+
+        // One byte a read, as a slow pipe can give
+        let mut reader = BufReader::with_capacity(1, Cursor::new(&written));
+        let raw = RawHeader::read(&mut reader).unwrap().0.unwrap();
+        assert_eq!(raw, header.to_raw());
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "哈基米");
+
+        let mut reader = BufReader::with_capacity(1, Cursor::new("哈基米曼波"));
+        assert_eq!(RawHeader::read(&mut reader).unwrap(), (None, Vec::new()));
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "哈基米曼波");
+
+        // Honey water of pinyin tokens can begin like the magic
+        let mut reader = BufReader::with_capacity(1, Cursor::new("hajimihajimi"));
+        let (raw, mut consumed) = RawHeader::read(&mut reader).unwrap();
+        assert!(raw.is_none());
+        reader.read_to_end(&mut consumed).unwrap();
+        assert_eq!(consumed, b"hajimihajimi");
+    }
+
+    #[test]
+    fn test_read_headerless() {
+        let mut reader = Cursor::new("哈基米曼波");
+        assert!(Header::<Bits8>::read(&mut reader).unwrap().0.is_none());
+        assert_eq!(reader.position(), 0);
+    }
+
+    #[test]
+    fn test_read_bad_header() {
+        let encoding =
+            JimiEncoding::<Bits8>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let mut written = Vec::new();
+        Header::new(encoding)
+            .with_codebook(Codebook::Uniform)
+            .write(&mut written, false)
+            .unwrap();
+
+        let read = |bytes: &[u8]| Header::<Bits8>::read(Cursor::new(bytes));
+
+        let mut corrupted = written.clone();
+        let i = corrupted.len() - 10;
+        corrupted[i] ^= 1;
+        assert!(matches!(
+            read(&corrupted),
+            Err(ContainerError::ChecksumMismatch { .. })
+        ));
+
+        let newer =
+            String::from_utf8(written.clone())
+                .unwrap()
+                .replacen("hajiman/2", "hajiman/9", 1);
+        assert!(matches!(
+            read(newer.as_bytes()),
+            Err(ContainerError::UnsupportedVersion(v)) if v == "9"
+        ));
+
+        assert!(matches!(
+            read(&written[..written.len() - 5]),
+            Err(ContainerError::Malformed(_))
+        ));
+        assert!(matches!(
+            read(b"{\"encoding\": 1}\n"),
+            Err(ContainerError::Malformed(_))
+        ));
+
+        assert!(matches!(
+            Header::<Bits6>::read(Cursor::new(&written)),
+            Err(ContainerError::WidthMismatch {
+                expected: 6,
+                found: 8
+            })
+        ));
+    }
+}
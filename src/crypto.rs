@@ -0,0 +1,234 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 12;
+
+/// Argon2id parameters turning a passphrase into a key
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Kdf {
+    /// Memory in KiB
+    pub m_cost: u32,
+    /// Number of passes
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+    #[serde(with = "hex")]
+    pub salt: Vec<u8>,
+}
+
+impl Kdf {
+    /// Most memory in KiB a header may ask for, 1 GiB
+    pub const MAX_M_COST: u32 = 1 << 20;
+    pub const MAX_T_COST: u32 = 16;
+    pub const MAX_P_COST: u32 = 16;
+
+    /// Argon2id with its default costs and `salt`
+    pub fn new(salt: Vec<u8>) -> Self {
+        Self {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            salt,
+        }
+    }
+
+    /// `Err` naming the first cost above its maximum, as costs read from a header
+    /// could otherwise make deriving the key take all memory or time
+    pub fn check_costs(&self) -> Result<(), String> {
+        for (name, cost, max) in [
+            ("m_cost", self.m_cost, Self::MAX_M_COST),
+            ("t_cost", self.t_cost, Self::MAX_T_COST),
+            ("p_cost", self.p_cost, Self::MAX_P_COST),
+        ] {
+            if cost > max {
+                return Err(format!(
+                    "KDF {} {} is above the maximum {}",
+                    name, cost, max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn derive(&self, passphrase: &str) -> Result<[u8; 32], CryptoError> {
+        self.check_costs().map_err(CryptoError::Kdf)?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map_err(|e| CryptoError::Kdf(e.to_string()))?;
+        let mut key = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(|e| CryptoError::Kdf(e.to_string()))?;
+        Ok(key)
+    }
+}
+
+/// How a payload is encrypted with ChaCha20-Poly1305, everything but the passphrase
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Encryption {
+    pub kdf: Kdf,
+    #[serde(with = "hex")]
+    pub nonce: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum CryptoError {
+    Random(String),
+    Kdf(String),
+    BadNonce(usize),
+    /// Wrong passphrase, or the ciphertext was altered
+    Authentication,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::Random(e) => write!(f, "no randomness for salt and nonce: {}", e),
+            CryptoError::Kdf(e) => write!(f, "derive key from passphrase failed: {}", e),
+            CryptoError::BadNonce(len) => {
+                write!(f, "nonce is {} bytes, expected {}", len, NONCE_LEN)
+            }
+            CryptoError::Authentication => {
+                write!(f, "decryption failed: wrong passphrase or corrupted data")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
+impl Encryption {
+    pub fn new(kdf: Kdf, nonce: Vec<u8>) -> Self {
+        Self { kdf, nonce }
+    }
+
+    /// Default KDF with a fresh random salt and nonce, which must not be reused
+    pub fn random() -> Result<Self, CryptoError> {
+        let mut salt = vec![0; SALT_LEN];
+        let mut nonce = vec![0; NONCE_LEN];
+        getrandom::getrandom(&mut salt)
+            .and_then(|_| getrandom::getrandom(&mut nonce))
+            .map_err(|e| CryptoError::Random(e.to_string()))?;
+        Ok(Self::new(Kdf::new(salt), nonce))
+    }
+
+    fn cipher(&self, passphrase: &str) -> Result<(ChaCha20Poly1305, &Nonce), CryptoError> {
+        if self.nonce.len() != NONCE_LEN {
+            return Err(CryptoError::BadNonce(self.nonce.len()));
+        }
+        let key = self.kdf.derive(passphrase)?;
+        Ok((
+            ChaCha20Poly1305::new(&key.into()),
+            Nonce::from_slice(&self.nonce),
+        ))
+    }
+
+    /// Ciphertext of `plaintext` followed by the authentication tag
+    pub fn encrypt(&self, passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (cipher, nonce) = self.cipher(passphrase)?;
+        Ok(cipher
+            .encrypt(nonce, plaintext)
+            .expect("payloads fit in ChaCha20-Poly1305"))
+    }
+
+    pub fn decrypt(&self, passphrase: &str, ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let (cipher, nonce) = self.cipher(passphrase)?;
+        cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|_| CryptoError::Authentication)
+    }
+}
+
+/// Bytes written as lowercase hex strings
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let s: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s.len() % 2 != 0 {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..s.len())
+            .step_by(2)
+            .map(|i| {
+                s.get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| D::Error::custom(format!("bad hex {:?}", s)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Bytes the authentication tag adds to the ciphertext
+    const TAG_LEN: usize = 16;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Cheap parameters, so the tests stay fast
+    fn test_encryption() -> Encryption {
+        Encryption::new(
+            Kdf {
+                m_cost: 64,
+                t_cost: 1,
+                p_cost: 1,
+                salt: b"honeywatersalt!!".to_vec(),
+            },
+            b"hajimi nonce".to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_encryption_vectors() {
+        let encryption = test_encryption();
+        assert_eq!(hex(&encryption.kdf.derive("哈基米").unwrap()), KEY_VECTOR,);
+
+        let ciphertext = encryption.encrypt("哈基米", b"honey water").unwrap();
+        assert_eq!(ciphertext.len(), b"honey water".len() + TAG_LEN);
+        assert_eq!(hex(&ciphertext), CIPHERTEXT_VECTOR);
+        assert_eq!(
+            encryption.decrypt("哈基米", &ciphertext).unwrap(),
+            b"honey water"
+        );
+
+        assert!(matches!(
+            encryption.decrypt("曼波", &ciphertext),
+            Err(CryptoError::Authentication)
+        ));
+        let mut tampered = ciphertext;
+        tampered[0] ^= 1;
+        assert!(matches!(
+            encryption.decrypt("哈基米", &tampered),
+            Err(CryptoError::Authentication)
+        ));
+    }
+
+    #[test]
+    fn test_serialize_encryption() {
+        let encryption = test_encryption();
+        let json = serde_json::to_value(&encryption).unwrap();
+        assert_eq!(json["nonce"], hex(b"hajimi nonce"));
+        assert_eq!(
+            serde_json::from_value::<Encryption>(json).unwrap(),
+            encryption
+        );
+
+        let random = Encryption::random().unwrap();
+        assert_ne!(random.nonce, Encryption::random().unwrap().nonce);
+    }
+
+    // Checked against the Argon2id and ChaCha20-Poly1305 of OpenSSL
+    const KEY_VECTOR: &str = "98b409012cf702c16646957796a22acf22eedfa5cdf8d5427b800cb12a7dfd74";
+    const CIPHERTEXT_VECTOR: &str = "c57fb1f33db7d80832a48569f010433ea0dcafa9874762a58c24de";
+}
@@ -0,0 +1,157 @@
+use std::cell::Cell;
+
+use crate::bits_key::Bits;
+use crate::dyn_jimi::{DynJimiDecoder, DynJimiEncoding, WIDTHS};
+use crate::jimi::JimiDecoder;
+use crate::token_set::TOKEN_SETS;
+
+/// How well a candidate encoding decodes a sample of honey water without a header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Guess {
+    /// Index of the encoding among the candidates
+    pub candidate: usize,
+    /// Fraction of the sample that lexes and decodes before the first error
+    pub clean: f32,
+    /// Whether the whole sample decodes and ends on a whole group of symbols, as
+    /// honey water does
+    pub complete: bool,
+    /// Whether the decoded bytes are valid UTF-8, as far as they go
+    pub utf8: bool,
+    /// Entropy of the decoded bytes, in bits per byte
+    pub entropy: f32,
+}
+
+impl Guess {
+    /// Between 0 and 1, mostly how much of the sample decodes cleanly, and then how
+    /// plausible the decoded bytes are
+    pub fn score(&self) -> f32 {
+        let plausibility =
+            self.complete as u8 as f32 + self.utf8 as u8 as f32 + (1.0 - self.entropy / 8.0);
+        (4.0 * self.clean + plausibility) / 7.0
+    }
+}
+
+/// Uniform encodings of every built-in token set and supported width, the default
+/// hajimi 8-bit one first
+pub fn uniform_candidates() -> Vec<DynJimiEncoding> {
+    TOKEN_SETS
+        .iter()
+        .flat_map(|set| {
+            WIDTHS.iter().rev().map(move |&bits| {
+                DynJimiEncoding::from_token_set(bits, set, None).expect("widths are supported")
+            })
+        })
+        .collect()
+}
+
+/// Try decoding `sample` with each of `candidates`, best guess first.
+///
+/// Candidates whose tokens cannot be told apart are left out, and ties go to the
+/// earlier candidate. The sample may be cut short of the honey water it comes from.
+pub fn rank(sample: &str, candidates: &[DynJimiEncoding]) -> Vec<Guess> {
+    let mut guesses: Vec<Guess> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(candidate, encoding)| {
+            let decoder = encoding.decoder().ok()?;
+            let (consumed, complete, bytes) = match &decoder {
+                DynJimiDecoder::Bits4(decoder) => decode_sample(decoder, sample),
+                DynJimiDecoder::Bits6(decoder) => decode_sample(decoder, sample),
+                DynJimiDecoder::Bits8(decoder) => decode_sample(decoder, sample),
+            };
+            Some(Guess {
+                candidate,
+                clean: match sample.len() {
+                    0 => 0.0,
+                    len => consumed as f32 / len as f32,
+                },
+                complete,
+                // A character cut off at the end of the sample is fine
+                utf8: std::str::from_utf8(&bytes)
+                    .map_or_else(|e| e.error_len().is_none(), |_| true),
+                entropy: entropy(&bytes),
+            })
+        })
+        .collect();
+    guesses.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    guesses
+}
+
+/// Bytes of `sample` that decode before the first error, how many bytes of it were
+/// read by then, and whether there was no error
+fn decode_sample<B: Bits>(decoder: &JimiDecoder<B>, sample: &str) -> (usize, bool, Vec<u8>) {
+    let consumed = Cell::new(0);
+    let chars = sample
+        .chars()
+        .inspect(|c| consumed.set(consumed.get() + c.len_utf8()));
+    let mut symbols = Vec::new();
+    let mut failed = false;
+    for symbol in decoder.decode_chars(chars) {
+        match symbol {
+            Ok(symbol) => symbols.push(symbol),
+            Err(_) => {
+                failed = true;
+                break;
+            }
+        }
+    }
+
+    let complete = !failed && symbols.len() % B::group_len() == 0;
+    let mut bytes = Vec::new();
+    B::concat(symbols.into_iter().map(Ok::<_, !>), &mut bytes)
+        .expect("writing to a vector should not fail");
+    (consumed.get(), complete, bytes)
+}
+
+fn entropy(bytes: &[u8]) -> f32 {
+    let mut counts = [0usize; 256];
+    for &b in bytes {
+        counts[b as usize] += 1;
+    }
+    counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| n as f32 / bytes.len() as f32)
+        .map(|p| -p * p.log2())
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TokenSet;
+
+    #[test]
+    fn test_rank() {
+        let text = "Honey water, honey water, 哈基米 drinks honey water.".repeat(4);
+        let candidates = uniform_candidates();
+        for (i, encoding) in candidates.iter().enumerate() {
+            let name = encoding.token_set().unwrap().name();
+            let honey_water = encoding.encoder().encode(text.as_bytes());
+            let best = &rank(&honey_water, &candidates)[0];
+            assert_eq!(best.clean, 1.0);
+            assert!(best.complete && best.utf8);
+            // Pinyin spellings are aliases of hajimi letters, and bits of some widths
+            // are spelled alike with two letters, so several guesses can be right
+            if name != "hajimi-pinyin" && name != "binary" {
+                assert_eq!(best.candidate, i, "{} {}", name, encoding.bits());
+            }
+            let decoded = candidates[best.candidate]
+                .decoder()
+                .unwrap()
+                .decode(&honey_water, None)
+                .unwrap();
+            assert!(
+                decoded.starts_with(text.as_bytes()),
+                "{} {}",
+                name,
+                encoding.bits()
+            );
+        }
+
+        let morse =
+            DynJimiEncoding::from_token_set(8, TokenSet::by_name("morse").unwrap(), None).unwrap();
+        let guesses = rank("哈基米 not honey water", &[morse]);
+        assert!(guesses[0].clean < 0.5);
+    }
+}
@@ -0,0 +1,358 @@
+use crate::bits::{Bits4, Bits6, Bits8};
+use crate::bits_key::{Bits, ConcatError};
+use crate::characters::{CharacterCounter, CharacterFrequency};
+use crate::jimi::{JimiDecoder, JimiEncoder, JimiEncoding, JimiError};
+use crate::key::Key;
+use crate::letters::LetterIdIndexed;
+use crate::lexing::LexemError;
+use crate::lexing::normalize::Normalization;
+use crate::token_set::TokenSet;
+
+/// Symbol widths the `Dyn` types can be built with, which [`with_width`] dispatches on
+pub const WIDTHS: [u32; 3] = [Bits4::N, Bits6::N, Bits8::N];
+
+/// Evaluate `$body` with `$B` standing for the symbols `$bits` wide, or else fail with
+/// [`UnsupportedWidth`] converted into the error `$body` returns.
+///
+/// This is the one place widths are dispatched on, along with the variants of the
+/// `Dyn` types.
+macro_rules! with_width {
+    ($bits:expr, $B:ident => $body:expr) => {
+        match $bits {
+            4 => {
+                type $B = $crate::bits::Bits4;
+                $body
+            }
+            6 => {
+                type $B = $crate::bits::Bits6;
+                $body
+            }
+            8 => {
+                type $B = $crate::bits::Bits8;
+                $body
+            }
+            bits => Err($crate::dyn_jimi::UnsupportedWidth(bits).into()),
+        }
+    };
+}
+pub(crate) use with_width;
+
+/// Symbol width asked for at runtime which is not one of [`WIDTHS`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedWidth(pub u32);
+
+impl std::fmt::Display for UnsupportedWidth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-bit symbols are not supported, expected one of {:?}",
+            self.0, WIDTHS
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedWidth {}
+
+/// [`JimiEncoding`] of any supported width, for when the width is only known at runtime.
+///
+/// Serialized like a [`JimiEncoding`] with a `bits` field recording the width, which
+/// is taken to be 8 when missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynJimiEncoding {
+    Bits4(JimiEncoding<Bits4>),
+    Bits6(JimiEncoding<Bits6>),
+    Bits8(JimiEncoding<Bits8>),
+}
+
+/// [`JimiEncoder`] of any supported width
+#[derive(Debug, Clone)]
+pub enum DynJimiEncoder {
+    Bits4(JimiEncoder<Bits4>),
+    Bits6(JimiEncoder<Bits6>),
+    Bits8(JimiEncoder<Bits8>),
+}
+
+/// [`JimiDecoder`] of any supported width
+#[derive(Debug, Clone)]
+pub enum DynJimiDecoder {
+    Bits4(JimiDecoder<Bits4>),
+    Bits6(JimiDecoder<Bits6>),
+    Bits8(JimiDecoder<Bits8>),
+}
+
+/// Evaluate `$body` with `$inner` bound to what `$value` wraps, whatever its width
+macro_rules! dispatch {
+    ($value:expr, $inner:ident => $body:expr) => {
+        match $value {
+            Self::Bits4($inner) => $body,
+            Self::Bits6($inner) => $body,
+            Self::Bits8($inner) => $body,
+        }
+    };
+}
+
+/// Like [`dispatch`], wrapping the result in the variant of `$Target` of the same width
+macro_rules! map_width {
+    ($value:expr, $Target:ident, $inner:ident => $body:expr) => {
+        match $value {
+            Self::Bits4($inner) => $Target::Bits4($body),
+            Self::Bits6($inner) => $Target::Bits6($body),
+            Self::Bits8($inner) => $Target::Bits8($body),
+        }
+    };
+}
+
+/// Evaluate `$body` with `$B` standing for the symbols `$bits` wide, and wrap the
+/// encoding it builds
+macro_rules! build {
+    ($bits:expr, $B:ident => $body:expr) => {
+        with_width!($bits, $B => Ok::<_, UnsupportedWidth>(DynJimiEncoding::from($body)))
+    };
+}
+
+/// Frequency of symbols in `sample`, or uniform if there is nothing to count
+fn frequency<B: Bits>(sample: Option<&[u8]>) -> CharacterFrequency<B> {
+    match sample {
+        Some(sample) if !sample.is_empty() => {
+            let mut counter = CharacterCounter::empty();
+            counter.count(B::iter_bytes(sample).data);
+            counter.finish()
+        }
+        _ => CharacterFrequency::all_equal(),
+    }
+}
+
+impl DynJimiEncoding {
+    /// Like [`JimiEncoding::new`], for `bits` wide symbols as frequent as in `sample`,
+    /// or equally frequent without one
+    pub fn new(
+        bits: u32,
+        tokens: LetterIdIndexed<String>,
+        sample: Option<&[u8]>,
+    ) -> Result<Self, UnsupportedWidth> {
+        build!(bits, B => JimiEncoding::new(tokens, &frequency::<B>(sample)))
+    }
+
+    /// Like [`DynJimiEncoding::new`], with [`JimiEncoding::with_aliases`]
+    pub fn with_aliases(
+        bits: u32,
+        tokens: LetterIdIndexed<Vec<String>>,
+        sample: Option<&[u8]>,
+    ) -> Result<Self, UnsupportedWidth> {
+        build!(bits, B => JimiEncoding::with_aliases(tokens, &frequency::<B>(sample)))
+    }
+
+    /// Like [`DynJimiEncoding::new`], with [`JimiEncoding::from_token_set`]
+    pub fn from_token_set(
+        bits: u32,
+        set: &'static TokenSet,
+        sample: Option<&[u8]>,
+    ) -> Result<Self, UnsupportedWidth> {
+        build!(bits, B => JimiEncoding::from_token_set(set, &frequency::<B>(sample)))
+    }
+
+    /// Width of symbols in bits
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Bits4(_) => Bits4::N,
+            Self::Bits6(_) => Bits6::N,
+            Self::Bits8(_) => Bits8::N,
+        }
+    }
+
+    pub fn encoder(&self) -> DynJimiEncoder {
+        map_width!(self, DynJimiEncoder, encoding => encoding.encoder())
+    }
+
+    pub fn decoder(&self) -> Result<DynJimiDecoder, LexemError> {
+        Ok(map_width!(self, DynJimiDecoder, encoding => encoding.decoder()?))
+    }
+
+    /// See [`JimiEncoding::keyed`]
+    pub fn keyed(&self, key: &Key, shuffle_tokens: bool) -> Self {
+        map_width!(self, Self, encoding => encoding.keyed(key, shuffle_tokens))
+    }
+
+    /// See [`JimiEncoding::with_normalization`]
+    pub fn with_normalization(self, normalization: Normalization) -> Self {
+        map_width!(self, Self, encoding => encoding.with_normalization(normalization))
+    }
+
+    /// Spellings of each letter, the first of which is emitted when encoding
+    pub fn tokens(&self) -> &LetterIdIndexed<Vec<String>> {
+        dispatch!(self, encoding => encoding.tokens())
+    }
+
+    /// The built-in token set tokens come from, if any
+    pub fn token_set(&self) -> Option<&'static TokenSet> {
+        dispatch!(self, encoding => encoding.token_set())
+    }
+
+    pub fn normalization(&self) -> &Normalization {
+        dispatch!(self, encoding => encoding.normalization())
+    }
+}
+
+impl From<JimiEncoding<Bits4>> for DynJimiEncoding {
+    fn from(value: JimiEncoding<Bits4>) -> Self {
+        Self::Bits4(value)
+    }
+}
+
+impl From<JimiEncoding<Bits6>> for DynJimiEncoding {
+    fn from(value: JimiEncoding<Bits6>) -> Self {
+        Self::Bits6(value)
+    }
+}
+
+impl From<JimiEncoding<Bits8>> for DynJimiEncoding {
+    fn from(value: JimiEncoding<Bits8>) -> Self {
+        Self::Bits8(value)
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Tagged<'a, B>
+where
+    B: Bits + serde::Serialize,
+{
+    bits: u32,
+    #[serde(flatten)]
+    encoding: &'a JimiEncoding<B>,
+}
+
+impl serde::Serialize for DynJimiEncoding {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let bits = self.bits();
+        dispatch!(self, encoding => Tagged { bits, encoding }.serialize(serializer))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DynJimiEncoding {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        // The width decides how the rest is read, so it is looked at first
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let bits = match value.get("bits") {
+            Some(bits) => bits
+                .as_u64()
+                .and_then(|bits| bits.try_into().ok())
+                .ok_or_else(|| D::Error::custom(format!("invalid symbol width {}", bits)))?,
+            None => Bits8::N,
+        };
+        build!(bits, B => serde_json::from_value::<JimiEncoding<B>>(value).map_err(D::Error::custom)?)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl DynJimiEncoder {
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Bits4(_) => Bits4::N,
+            Self::Bits6(_) => Bits6::N,
+            Self::Bits8(_) => Bits8::N,
+        }
+    }
+
+    /// Honey water of `bytes`, whose last group of symbols is padded with zero bits
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        dispatch!(self, encoder => encoder.encode(bytes).data.collect())
+    }
+}
+
+impl DynJimiDecoder {
+    pub fn bits(&self) -> u32 {
+        match self {
+            Self::Bits4(_) => Bits4::N,
+            Self::Bits6(_) => Bits6::N,
+            Self::Bits8(_) => Bits8::N,
+        }
+    }
+
+    /// Bytes of honey water `s`, cut to `length` if given.
+    ///
+    /// Without a length, zero bytes padding the last group of symbols are kept, which
+    /// happens when symbols do not divide bytes evenly.
+    pub fn decode(&self, s: &str, length: Option<usize>) -> Result<Vec<u8>, JimiError> {
+        let mut bytes =
+            dispatch!(self, decoder => decoder.decode_to_vec(s)).map_err(|e| match e {
+                ConcatError::Parent(e) => e,
+                ConcatError::Io(_) => unreachable!("writing to a vector does not fail"),
+            })?;
+        if let Some(length) = length {
+            bytes.truncate(length);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hajimi_tokens;
+
+    #[test]
+    fn test_roundtrip() {
+        let sample = "哈基米 honey water".as_bytes();
+        for bits in WIDTHS {
+            for input in [&b""[..], b"a", b"ab", b"abc", sample] {
+                let encoding = DynJimiEncoding::new(bits, hajimi_tokens(), Some(sample)).unwrap();
+                assert_eq!(encoding.bits(), bits);
+                let encoded = encoding.encoder().encode(input);
+                let decoder = encoding.decoder().unwrap();
+                assert_eq!(
+                    decoder.decode(&encoded, Some(input.len())).unwrap(),
+                    input,
+                    "{bits}"
+                );
+                assert!(decoder.decode(&encoded, None).unwrap().starts_with(input));
+            }
+        }
+        assert_eq!(
+            DynJimiEncoding::new(5, hajimi_tokens(), None).unwrap_err(),
+            UnsupportedWidth(5)
+        );
+    }
+
+    #[test]
+    fn test_with_width() {
+        for bits in WIDTHS {
+            let width = with_width!(bits, B => Ok::<_, UnsupportedWidth>(B::N)).unwrap();
+            assert_eq!(width, bits);
+        }
+        assert_eq!(
+            with_width!(5, B => Ok::<_, UnsupportedWidth>(B::N)),
+            Err(UnsupportedWidth(5))
+        );
+    }
+
+    #[test]
+    fn test_serialize() {
+        let set = TokenSet::by_name("hajimi").unwrap();
+        for bits in WIDTHS {
+            let encoding = DynJimiEncoding::from_token_set(bits, set, Some(b"honey")).unwrap();
+            let json = serde_json::to_value(&encoding).unwrap();
+            assert_eq!(json["bits"], bits);
+            let parsed: DynJimiEncoding = serde_json::from_value(json).unwrap();
+            assert_eq!(parsed, encoding);
+        }
+
+        // Encodings saved by themselves do not record a width, and were all 8-bit
+        let bare = JimiEncoding::<Bits8>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let parsed: DynJimiEncoding =
+            serde_json::from_value(serde_json::to_value(&bare).unwrap()).unwrap();
+        assert_eq!(parsed, DynJimiEncoding::Bits8(bare));
+
+        let mut json =
+            serde_json::to_value(DynJimiEncoding::new(6, hajimi_tokens(), None).unwrap()).unwrap();
+        json["bits"] = 8.into();
+        assert!(serde_json::from_value::<DynJimiEncoding>(json).is_err());
+    }
+}
@@ -2,7 +2,7 @@ use std::collections::BTreeSet;
 
 use crate::bits_key::{Bits, BitsIter, BitsMap};
 use crate::characters::CharacterFrequency;
-use crate::letters::{Code, LetterCosts, LetterId};
+use crate::letters::{Code, LetterCosts, LetterId, LetterIdIndexed};
 
 struct EncodingBuilder<B> {
     char2code: BitsMap<B, Option<Code>>,
@@ -38,21 +38,27 @@ where
             let apl = l.prev().map(|p| characters.accu_freq(p)).unwrap_or(0.0);
             let apr = characters.accu_freq(r.clone());
 
-            let mut partitions = self.letters.map(|m| {
-                let lm = apl
-                    + (apr - apl)
-                        * m.before()
-                            .map(|j| self.letters.c().powi(self.letters.cost(j)))
-                            .sum::<f32>();
-                let rm = lm + (apr - apl) * self.letters.c().powi(self.letters.cost(m));
-
-                BitsIter::<B>::closed_interval(l.clone(), r.clone())
-                    .filter(|char| {
-                        let s = characters.accu_freq2(char.clone());
-                        lm <= s && s < rm
-                    })
-                    .collect::<BTreeSet<_>>()
+            // Each letter gets the share of [apl, apr) starting at its lower bound and
+            // ending at the next one, so that no character falls in two shares or in
+            // none however floats round
+            let lower = self.letters.map(|m| {
+                apl + (apr - apl)
+                    * m.before()
+                        .map(|j| self.letters.c().powi(self.letters.cost(j)))
+                        .sum::<f32>()
             });
+            let first = self.letters.letters().next().unwrap();
+            let mut partitions = self.letters.map(|_| BTreeSet::new());
+            for char in BitsIter::<B>::closed_interval(l.clone(), r.clone()) {
+                let s = characters.accu_freq2(char.clone());
+                let m = self
+                    .letters
+                    .letters()
+                    .rev()
+                    .find(|&m| lower[m] <= s)
+                    .unwrap_or(first);
+                partitions[m].insert(char);
+            }
 
             if partitions.first().unwrap().is_empty() {
                 let m = self
@@ -133,6 +139,47 @@ where
     pub fn char2code(&self) -> &BitsMap<B, Code> {
         &self.char2code
     }
+
+    pub fn n_letters(&self) -> LetterId {
+        self.n_letters
+    }
+
+    /// Give each symbol the code of `symbols[symbol]`, with letters renamed by `letters`.
+    ///
+    /// Both have to be permutations for the result to stay a prefix code.
+    pub fn permuted(&self, symbols: &BitsMap<B, B>, letters: &LetterIdIndexed<LetterId>) -> Self {
+        Self {
+            char2code: symbols
+                .map(|_, to| Code::new(self.char2code[to.clone()].iter().map(|&l| letters[l]))),
+            n_letters: self.n_letters,
+        }
+    }
+
+    /// Check that codes only use known letters and none is a prefix of another,
+    /// which deserialized encodings are not guaranteed to satisfy
+    pub fn validate(&self) -> Result<(), String> {
+        let mut codes = self
+            .char2code
+            .iter()
+            .map(|(_, code)| code.iter().copied().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        if codes
+            .iter()
+            .any(|code| code.iter().any(|&letter| letter >= self.n_letters))
+        {
+            return Err("code uses letter beyond the number of letters".to_string());
+        }
+
+        codes.sort();
+        if codes.len() > 1 && codes.iter().any(|code| code.is_empty()) {
+            return Err("empty code".to_string());
+        }
+        if let Some(pair) = codes.windows(2).find(|pair| pair[1].starts_with(&pair[0])) {
+            return Err(format!("code {:?} is a prefix of {:?}", pair[0], pair[1]));
+        }
+        Ok(())
+    }
 }
 
 impl<B> Encoding<B>
@@ -149,3 +196,31 @@ pub mod encoder;
 
 pub use decoder::Decoder;
 pub use encoder::Encoder;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bits::{Bits4, Bits6, Bits8};
+    use crate::characters::CharacterCounter;
+    use crate::token_set::TokenSet;
+
+    fn test_skewed_frequency<B: Bits>() {
+        // Shares of rare symbols round to the same float, which used to put them in
+        // two partitions at once
+        let mut counter = CharacterCounter::<B>::empty();
+        counter.count(B::iter_bytes(&[0; 3000]).data);
+        let freq = counter.finish();
+        for set in TokenSet::all() {
+            let costs = set.canonical_tokens().map(|_, t| t.len() as i32);
+            let encoding = Encoding::build(LetterCosts::build(costs).unwrap(), &freq);
+            assert_eq!(encoding.validate(), Ok(()), "{} {}", set.name(), B::N);
+        }
+    }
+
+    #[test]
+    fn test_skewed_frequency_all_widths() {
+        test_skewed_frequency::<Bits8>();
+        test_skewed_frequency::<Bits6>();
+        test_skewed_frequency::<Bits4>();
+    }
+}
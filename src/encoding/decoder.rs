@@ -58,6 +58,45 @@ where
     pub fn decode<It: Iterator<Item = LetterId>>(&self, letters: It) -> Iter<'_, B, It> {
         lexing::iter::LexingIter::new(&self.roots, letters)
     }
+
+    /// Children of the node reached by following `prefix` from the roots
+    fn node(&self, prefix: &[LetterId]) -> Option<&LetterIdIndexed<Tree<B>>> {
+        prefix.iter().try_fold(&self.roots, |node, &letter| {
+            match node.get(letter)?.deref() {
+                lexing::Tree::Inner(children, _) => Some(children),
+                _ => None,
+            }
+        })
+    }
+
+    /// Whether `prefix` is a proper prefix of some code
+    pub fn is_prefix(&self, prefix: &[LetterId]) -> bool {
+        self.node(prefix).is_some()
+    }
+
+    /// Feed `letter` to the code tree, where `prefix` holds letters of the
+    /// current code read so far and must satisfy [`Decoder::is_prefix`].
+    pub fn step(
+        &self,
+        prefix: &mut Vec<LetterId>,
+        letter: LetterId,
+    ) -> Result<Option<B>, lexing::iter::Error<LetterId>> {
+        let node = self
+            .node(prefix)
+            .expect("prefix should lead to an inner node");
+        match node.get(letter).map(|t| t.deref()) {
+            Some(lexing::Tree::Leaf(b)) => {
+                prefix.clear();
+                Ok(Some(b.clone()))
+            }
+            Some(lexing::Tree::Inner(..)) => {
+                prefix.push(letter);
+                Ok(None)
+            }
+            Some(lexing::Tree::Invalid) => Err(lexing::Error::Unexpected(prefix.clone(), letter)),
+            None => Err(lexing::Error::Invalid(letter)),
+        }
+    }
 }
 
 #[cfg(test)]
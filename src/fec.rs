@@ -0,0 +1,431 @@
+use std::io::Write;
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+/// Shard length used unless told otherwise
+pub const DEFAULT_SHARD_LEN: usize = 32;
+/// Longest shard accepted, so that a header cannot make a block fill memory
+pub const MAX_SHARD_LEN: usize = 1 << 16;
+/// Most shards a block can have over GF(256)
+const MAX_SHARDS: usize = 256;
+/// Bytes framing each shard: its index before it, and a CRC32 after it
+const FRAME_OVERHEAD: usize = 8;
+
+/// How much redundancy the Reed–Solomon stage adds.
+///
+/// Data is cut into blocks of `data_shards` shards of `shard_len` bytes, and each block
+/// gets `parity_shards` more, so that up to `parity_shards` lost shards per block can be
+/// recovered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Redundancy {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub shard_len: usize,
+}
+
+impl Redundancy {
+    pub fn new(data_shards: usize, parity_shards: usize) -> Self {
+        Self {
+            data_shards,
+            parity_shards,
+            shard_len: DEFAULT_SHARD_LEN,
+        }
+    }
+
+    fn shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    /// Check that there is data, that shards are as many as GF(256) allows and that
+    /// they are neither empty nor longer than [`MAX_SHARD_LEN`], as redundancy read
+    /// from a header may be anything
+    pub fn validate(&self) -> Result<(), String> {
+        if self.data_shards == 0 {
+            return Err("there must be data shards".to_string());
+        }
+        match self.data_shards.checked_add(self.parity_shards) {
+            Some(shards) if shards <= MAX_SHARDS => {}
+            _ => {
+                return Err(format!(
+                    "{}+{} shards, at most {} are supported",
+                    self.data_shards, self.parity_shards, MAX_SHARDS
+                ));
+            }
+        }
+        if !(1..=MAX_SHARD_LEN).contains(&self.shard_len) {
+            return Err(format!(
+                "shards of {} bytes, expected 1 to {}",
+                self.shard_len, MAX_SHARD_LEN
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Redundancy {
+    type Err = String;
+
+    /// Parse `<data>+<parity>`, such as `16+4`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (data, parity) = s
+            .split_once('+')
+            .ok_or_else(|| format!("expected <data>+<parity>, found {:?}", s))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .map_err(|e| format!("bad number of shards {:?}: {}", n, e))
+        };
+        Ok(Self::new(parse(data)?, parse(parity)?))
+    }
+}
+
+#[derive(Debug)]
+pub enum FecError {
+    Io(std::io::Error),
+    InvalidRedundancy(String),
+    /// More shards of a block are lost than there are parity shards
+    TooManyErasures {
+        block: u64,
+        lost: usize,
+        parity: usize,
+    },
+}
+
+impl std::fmt::Display for FecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FecError::Io(e) => write!(f, "write output failed: {}", e),
+            FecError::InvalidRedundancy(e) => write!(f, "invalid redundancy: {}", e),
+            FecError::TooManyErasures {
+                block,
+                lost,
+                parity,
+            } => write!(
+                f,
+                "block {} lost {} shards, at most {} can be recovered",
+                block, lost, parity
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FecError {}
+
+impl From<std::io::Error> for FecError {
+    fn from(value: std::io::Error) -> Self {
+        FecError::Io(value)
+    }
+}
+
+/// What happened to the shards while decoding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FecStats {
+    /// Frames accepted
+    pub frames: usize,
+    /// Frames dropped because they are cut off, corrupted or out of order
+    pub rejected: usize,
+    /// Shards rebuilt from parity
+    pub recovered: usize,
+}
+
+/// Reed–Solomon erasure code over GF(256), applied to bytes before they are encoded
+/// into honey water.
+///
+/// Every shard is written as a frame carrying its index and a CRC32, so that frames
+/// which are lost or fail to decode are erasures at known positions.
+pub struct Fec {
+    redundancy: Redundancy,
+    rs: ReedSolomon,
+}
+
+impl Fec {
+    pub fn new(redundancy: Redundancy) -> Result<Self, FecError> {
+        redundancy.validate().map_err(FecError::InvalidRedundancy)?;
+        let rs =
+            ReedSolomon::new(redundancy.data_shards, redundancy.parity_shards).map_err(|e| {
+                FecError::InvalidRedundancy(format!(
+                    "{}+{} shards: {:?}",
+                    redundancy.data_shards, redundancy.parity_shards, e
+                ))
+            })?;
+        Ok(Self { redundancy, rs })
+    }
+
+    pub fn redundancy(&self) -> &Redundancy {
+        &self.redundancy
+    }
+
+    /// Number of data bytes in a block
+    pub fn block_len(&self) -> usize {
+        self.redundancy.data_shards * self.redundancy.shard_len
+    }
+
+    /// Number of bytes in each frame
+    pub fn frame_len(&self) -> usize {
+        self.redundancy.shard_len + FRAME_OVERHEAD
+    }
+
+    /// Frames of the `block`-th block, whose data is at most [`Fec::block_len`] bytes
+    /// and padded with zeros
+    pub fn encode_block(&self, block: u64, data: &[u8]) -> Vec<Vec<u8>> {
+        let Redundancy {
+            data_shards,
+            shard_len,
+            ..
+        } = self.redundancy;
+        assert!(data.len() <= self.block_len(), "block is too long");
+
+        let mut shards: Vec<Vec<u8>> = (0..self.redundancy.shards())
+            .map(|i| {
+                let mut shard = vec![0; shard_len];
+                if i < data_shards {
+                    let data = data.get(i * shard_len..).unwrap_or_default();
+                    let n = data.len().min(shard_len);
+                    shard[..n].copy_from_slice(&data[..n]);
+                }
+                shard
+            })
+            .collect();
+        self.rs
+            .encode(&mut shards)
+            .expect("shards are built to the codec's size");
+
+        let first = block * self.redundancy.shards() as u64;
+        shards
+            .into_iter()
+            .enumerate()
+            .map(|(i, shard)| {
+                let index = u32::try_from(first + i as u64).expect("too many shards");
+                let mut frame = Vec::with_capacity(self.frame_len());
+                frame.extend_from_slice(&index.to_be_bytes());
+                frame.extend_from_slice(&shard);
+                frame.extend_from_slice(&crc32fast::hash(&frame).to_be_bytes());
+                frame
+            })
+            .collect()
+    }
+
+    /// Check a frame, returning the shard index and the shard.
+    ///
+    /// Bytes after the frame are ignored, as they can be padding up to a whole group of
+    /// symbols.
+    fn parse_frame<'a>(&self, frame: &'a [u8]) -> Option<(u64, &'a [u8])> {
+        let frame = frame.get(..self.frame_len())?;
+        let (body, checksum) = frame.split_at(frame.len() - 4);
+        if crc32fast::hash(body).to_be_bytes() != checksum {
+            return None;
+        }
+        let (index, shard) = body.split_at(4);
+        Some((u32::from_be_bytes(index.try_into().unwrap()) as u64, shard))
+    }
+
+    pub fn decoder<W: Write>(&self, writer: W) -> FecDecoder<'_, W> {
+        FecDecoder {
+            fec: self,
+            writer,
+            block: 0,
+            shards: vec![None; self.redundancy.shards()],
+            stats: FecStats::default(),
+        }
+    }
+}
+
+/// Puts data back together from frames arriving in order, writing each block once
+/// a frame of a later block shows up.
+pub struct FecDecoder<'a, W> {
+    fec: &'a Fec,
+    writer: W,
+    block: u64,
+    shards: Vec<Option<Vec<u8>>>,
+    stats: FecStats,
+}
+
+impl<W> FecDecoder<'_, W>
+where
+    W: Write,
+{
+    /// Feed a frame. Frames that are corrupted are dropped, which is the same as never
+    /// feeding them.
+    pub fn push(&mut self, frame: &[u8]) -> Result<(), FecError> {
+        let Some((index, shard)) = self.fec.parse_frame(frame) else {
+            self.stats.rejected += 1;
+            return Ok(());
+        };
+        let n = self.fec.redundancy.shards() as u64;
+        let block = index / n;
+        if block < self.block {
+            self.stats.rejected += 1;
+            return Ok(());
+        }
+        while self.block < block {
+            self.flush()?;
+        }
+        self.shards[(index % n) as usize] = Some(shard.to_vec());
+        self.stats.frames += 1;
+        Ok(())
+    }
+
+    /// Write the remaining blocks, expecting `length` bytes of data in total if known.
+    ///
+    /// Without `length`, the data comes out padded to whole blocks.
+    pub fn finish(mut self, length: Option<u64>) -> Result<FecStats, FecError> {
+        let blocks = length.map(|length| length.div_ceil(self.fec.block_len() as u64));
+        match blocks {
+            Some(blocks) => {
+                while self.block < blocks {
+                    self.flush()?;
+                }
+            }
+            None if self.shards.iter().any(Option::is_some) => self.flush()?,
+            None => {}
+        }
+        self.writer.flush()?;
+        Ok(self.stats)
+    }
+
+    /// Rebuild the current block, write its data and move on to the next block
+    fn flush(&mut self) -> Result<(), FecError> {
+        let Redundancy {
+            data_shards,
+            parity_shards,
+            shard_len,
+        } = self.fec.redundancy;
+
+        let lost = self.shards.iter().filter(|s| s.is_none()).count();
+        if lost > parity_shards {
+            return Err(FecError::TooManyErasures {
+                block: self.block,
+                lost,
+                parity: parity_shards,
+            });
+        }
+        let lost_data = self.shards[..data_shards]
+            .iter()
+            .filter(|s| s.is_none())
+            .count();
+        if lost_data > 0 {
+            self.fec
+                .rs
+                .reconstruct_data(&mut self.shards)
+                .expect("enough shards are present");
+            self.stats.recovered += lost_data;
+        }
+
+        for shard in &mut self.shards[..data_shards] {
+            let shard = shard.take().expect("data shards are rebuilt");
+            debug_assert_eq!(shard.len(), shard_len);
+            self.writer.write_all(&shard)?;
+        }
+        self.shards.fill(None);
+        self.block += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frames(fec: &Fec, data: &[u8]) -> Vec<Vec<u8>> {
+        data.chunks(fec.block_len())
+            .enumerate()
+            .flat_map(|(i, block)| fec.encode_block(i as u64, block))
+            .collect()
+    }
+
+    fn decode(
+        fec: &Fec,
+        frames: impl IntoIterator<Item = Vec<u8>>,
+        length: Option<u64>,
+    ) -> Result<(Vec<u8>, FecStats), FecError> {
+        let mut out = Vec::new();
+        let mut decoder = fec.decoder(&mut out);
+        for frame in frames {
+            decoder.push(&frame)?;
+        }
+        let stats = decoder.finish(length)?;
+        Ok((out, stats))
+    }
+
+    #[test]
+    fn test_fec_recover_erasures() {
+        let fec = Fec::new("4+2".parse().unwrap()).unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let length = Some(data.len() as u64);
+        let frames = frames(&fec, &data);
+
+        let (decoded, stats) = decode(&fec, frames.clone(), length).unwrap();
+        assert_eq!(&decoded[..data.len()], &data);
+        assert_eq!(stats.recovered, 0);
+
+        // Lose two shards of each block, in different ways
+        let damaged = frames
+            .iter()
+            .enumerate()
+            .filter_map(|(i, frame)| match i % 6 {
+                0 => None,
+                3 => {
+                    let mut frame = frame.clone();
+                    frame[5] ^= 1;
+                    Some(frame)
+                }
+                _ => Some(frame.clone()),
+            });
+        let (decoded, stats) = decode(&fec, damaged, length).unwrap();
+        assert_eq!(&decoded[..data.len()], &data);
+        assert!(stats.recovered > 0);
+
+        let too_damaged = frames.into_iter().enumerate().filter(|(i, _)| i % 6 >= 3);
+        assert!(matches!(
+            decode(&fec, too_damaged.map(|(_, f)| f), length),
+            Err(FecError::TooManyErasures {
+                block: 0,
+                lost: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_fec_lost_last_block() {
+        let fec = Fec::new(Redundancy::new(2, 1)).unwrap();
+        let data = vec![42; fec.block_len() * 2];
+        let frames = frames(&fec, &data);
+
+        let (decoded, _) = decode(&fec, frames[..3].to_vec(), None).unwrap();
+        assert_eq!(decoded, data[..fec.block_len()]);
+        assert!(decode(&fec, frames[..3].to_vec(), Some(data.len() as u64)).is_err());
+    }
+
+    #[test]
+    fn test_parse_redundancy() {
+        assert_eq!("16+4".parse(), Ok(Redundancy::new(16, 4)));
+        assert!("16".parse::<Redundancy>().is_err());
+        assert!(Fec::new(Redundancy::new(4, 0)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_redundancy() {
+        let invalid = [
+            Redundancy::new(0, 4),
+            Redundancy::new(usize::MAX, 1),
+            Redundancy::new(200, 57),
+            Redundancy {
+                shard_len: 0,
+                ..Redundancy::new(4, 2)
+            },
+            Redundancy {
+                shard_len: usize::MAX,
+                ..Redundancy::new(4, 2)
+            },
+        ];
+        for redundancy in invalid {
+            assert!(
+                matches!(Fec::new(redundancy), Err(FecError::InvalidRedundancy(_))),
+                "{:?}",
+                redundancy
+            );
+        }
+        assert!(Fec::new(Redundancy::new(200, 56)).is_ok());
+    }
+}
@@ -1,16 +1,140 @@
 use crate::bits_key::{Bits, BitsMap, ConcatError, Padded};
 use crate::characters::CharacterFrequency;
 use crate::encoding::{Decoder, Encoding};
+use crate::key::Key;
 use crate::letters::{LetterCosts, LetterIdIndexed};
+use crate::lexing::normalize::Normalization;
 use crate::lexing::{self, LexemError, Lexer, StringLexer};
+use crate::token_set::TokenSet;
 
 #[derive(Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(
+    into = "serialized::JimiEncoding<B>",
+    try_from = "serialized::JimiEncoding<B>"
+)]
 pub struct JimiEncoding<B>
 where
     B: Bits,
 {
     encoding: Encoding<B>,
-    tokens: LetterIdIndexed<String>,
+    /// Spellings of each letter, the first of which is emitted when encoding
+    tokens: LetterIdIndexed<Vec<String>>,
+    /// Built-in set `tokens` come from, written by name instead of listing tokens
+    token_set: Option<&'static TokenSet>,
+    normalization: Normalization,
+}
+
+/// What encodings look like when serialized
+pub(crate) mod serialized {
+    use super::*;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    pub struct JimiEncoding<B>
+    where
+        B: Bits,
+    {
+        encoding: Encoding<B>,
+        tokens: Tokens,
+        #[serde(default, skip_serializing_if = "Normalization::is_none")]
+        normalization: Normalization,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(untagged)]
+    pub enum Tokens {
+        /// Name of a built-in token set
+        Named(String),
+        #[serde(deserialize_with = "spellings::deserialize")]
+        Listed(LetterIdIndexed<Vec<String>>),
+    }
+
+    impl Tokens {
+        pub fn new(tokens: &LetterIdIndexed<Vec<String>>, token_set: Option<&TokenSet>) -> Self {
+            match token_set {
+                Some(set) => Tokens::Named(set.name().to_string()),
+                None => Tokens::Listed(tokens.clone()),
+            }
+        }
+
+        pub fn resolve(
+            self,
+        ) -> Result<(LetterIdIndexed<Vec<String>>, Option<&'static TokenSet>), String> {
+            match self {
+                Tokens::Named(name) => {
+                    let set = TokenSet::by_name(&name)
+                        .ok_or_else(|| format!("unknown token set {:?}", name))?;
+                    Ok((set.tokens(), Some(set)))
+                }
+                Tokens::Listed(tokens) => Ok((tokens, None)),
+            }
+        }
+    }
+
+    impl<B> From<super::JimiEncoding<B>> for JimiEncoding<B>
+    where
+        B: Bits,
+    {
+        fn from(value: super::JimiEncoding<B>) -> Self {
+            Self {
+                encoding: value.encoding,
+                tokens: match value.token_set {
+                    Some(set) => Tokens::Named(set.name().to_string()),
+                    None => Tokens::Listed(value.tokens),
+                },
+                normalization: value.normalization,
+            }
+        }
+    }
+
+    impl<B> TryFrom<JimiEncoding<B>> for super::JimiEncoding<B>
+    where
+        B: Bits,
+    {
+        type Error = String;
+
+        fn try_from(value: JimiEncoding<B>) -> Result<Self, Self::Error> {
+            let (tokens, token_set) = value.tokens.resolve()?;
+            Ok(Self {
+                encoding: value.encoding,
+                tokens,
+                token_set,
+                normalization: value.normalization,
+            })
+        }
+    }
+}
+
+mod spellings {
+    use crate::letters::LetterIdIndexed;
+
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Spellings {
+        /// Encodings saved before aliases were supported
+        Canonical(String),
+        Aliases(Vec<String>),
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<LetterIdIndexed<Vec<String>>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::Deserialize;
+        use serde::de::Error;
+
+        let tokens = LetterIdIndexed::<Spellings>::deserialize(deserializer)?;
+        let tokens = tokens.map(|_, spellings| match spellings {
+            Spellings::Canonical(s) => vec![s],
+            Spellings::Aliases(v) => v,
+        });
+
+        if tokens.iter().any(|spellings| spellings.is_empty()) {
+            return Err(D::Error::custom(
+                "every letter should have at least one spelling",
+            ));
+        }
+        Ok(tokens)
+    }
 }
 
 impl<B> std::fmt::Debug for JimiEncoding<B>
@@ -24,7 +148,9 @@ where
                 "{:?}: {:?} {}",
                 b,
                 code,
-                code.iter().map(|i| &self.tokens[i][..]).collect::<String>()
+                code.iter()
+                    .map(|i| &self.tokens[i][0][..])
+                    .collect::<String>()
             )?;
         }
         Ok(())
@@ -43,14 +169,106 @@ where
         JimiDecoder::from_encoding(self)
     }
 
+    pub fn decode_state(&self) -> Result<JimiDecodeState<B>, LexemError> {
+        JimiDecodeState::new(self.clone())
+    }
+
     pub fn new(tokens: LetterIdIndexed<String>, freq: &CharacterFrequency<B>) -> Self {
-        let letters =
-            LetterCosts::build(tokens.map_by_ref(|_, s| s.len().try_into().unwrap())).unwrap();
+        Self::with_aliases(tokens.map(|_, s| vec![s]), freq)
+    }
+
+    /// Like [`JimiEncoding::new`], but each letter may be spelled in several ways
+    /// when decoding. Costs are those of the first spelling, which is the only one
+    /// emitted when encoding.
+    pub fn with_aliases(
+        tokens: LetterIdIndexed<Vec<String>>,
+        freq: &CharacterFrequency<B>,
+    ) -> Self {
+        let letters = LetterCosts::build(tokens.map_by_ref(|_, spellings| {
+            let canonical = spellings
+                .first()
+                .expect("every letter should have at least one spelling");
+            canonical.len().try_into().unwrap()
+        }))
+        .unwrap();
         Self {
             encoding: Encoding::build(letters, &freq),
             tokens,
+            token_set: None,
+            normalization: Normalization::default(),
         }
     }
+
+    /// Like [`JimiEncoding::with_aliases`] using a built-in token set, which is
+    /// referred to by name when serialized
+    pub fn from_token_set(set: &'static TokenSet, freq: &CharacterFrequency<B>) -> Self {
+        Self {
+            token_set: Some(set),
+            ..Self::with_aliases(set.tokens(), freq)
+        }
+    }
+
+    /// The built-in token set tokens come from, if any
+    pub fn token_set(&self) -> Option<&'static TokenSet> {
+        self.token_set
+    }
+
+    pub(crate) fn from_parts(
+        encoding: Encoding<B>,
+        tokens: LetterIdIndexed<Vec<String>>,
+        token_set: Option<&'static TokenSet>,
+    ) -> Self {
+        Self {
+            encoding,
+            tokens,
+            token_set,
+            normalization: Normalization::default(),
+        }
+    }
+
+    /// Encoding with symbols shuffled by `key`, and tokens of the same cost too if
+    /// `shuffle_tokens` is set, which cannot be decoded without the key.
+    ///
+    /// The tokens themselves are unchanged, only which code uses which of them.
+    pub fn keyed(&self, key: &Key, shuffle_tokens: bool) -> Self {
+        let letters = if shuffle_tokens {
+            key.letters(&self.tokens.map_by_ref(|_, spellings| spellings[0].len()))
+        } else {
+            self.tokens.map_by_ref(|id, _| id)
+        };
+        Self {
+            encoding: self.encoding.permuted(&key.symbols(), &letters),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) fn encoding(&self) -> &Encoding<B> {
+        &self.encoding
+    }
+
+    /// Spellings of each letter, the first of which is emitted when encoding
+    pub fn tokens(&self) -> &LetterIdIndexed<Vec<String>> {
+        &self.tokens
+    }
+
+    /// The spelling emitted for each letter when encoding
+    pub fn canonical_tokens(&self) -> LetterIdIndexed<String> {
+        self.tokens.map_by_ref(|_, spellings| spellings[0].clone())
+    }
+
+    /// Accept input matching tokens after `normalization`, when decoding.
+    ///
+    /// Tokens are still emitted as they are when encoding.
+    pub fn with_normalization(self, normalization: Normalization) -> Self {
+        Self {
+            normalization,
+            ..self
+        }
+    }
+
+    pub fn normalization(&self) -> &Normalization {
+        &self.normalization
+    }
 }
 
 mod encoder {
@@ -72,7 +290,7 @@ mod encoder {
             let char2code = encoding.encoding.char2code().map(|_, code| {
                 let offset = chunk.len();
                 for &letter_id in code.iter() {
-                    chunk.push_str(&encoding.tokens[letter_id]);
+                    chunk.push_str(&encoding.tokens[letter_id][0]);
                 }
                 let len = chunk.len() - offset;
                 (offset, len)
@@ -108,12 +326,118 @@ mod encoder {
                 original_length,
             }
         }
+
+        /// Honey water of a checksum trailer, to follow the encoded data
+        pub fn encode_checksum(&self, checksum: u32) -> impl Iterator<Item = &str> {
+            B::iter_bytes(&checksum.to_be_bytes())
+                .data
+                .map(|b| self.encode_bits(b))
+                .collect::<Vec<_>>()
+                .into_iter()
+        }
     }
 }
 
 pub use encoder::JimiEncoder;
 
+mod checksum {
+    use std::marker::PhantomData;
+
+    use super::*;
+
+    /// CRC32 of data as the decoder sees it, that is padded with zeros to whole
+    /// groups of symbols.
+    ///
+    /// Encoded after the data as a trailer of [`trailer_len`] symbols.
+    #[derive(Debug, Clone, Default)]
+    pub struct Checksum<B> {
+        hasher: crc32fast::Hasher,
+        len: usize,
+        _phantom: PhantomData<B>,
+    }
+
+    impl<B> Checksum<B>
+    where
+        B: Bits,
+    {
+        pub fn new() -> Self {
+            Self {
+                hasher: crc32fast::Hasher::new(),
+                len: 0,
+                _phantom: PhantomData,
+            }
+        }
+
+        pub fn update(&mut self, bytes: &[u8]) {
+            self.hasher.update(bytes);
+            self.len += bytes.len();
+        }
+
+        pub fn finish(mut self) -> u32 {
+            let group_bytes = B::group_bytes();
+            let padding = (group_bytes - self.len % group_bytes) % group_bytes;
+            self.hasher.update(&vec![0; padding]);
+            self.hasher.finalize()
+        }
+
+        pub fn of(bytes: &[u8]) -> u32 {
+            let mut checksum = Self::new();
+            checksum.update(bytes);
+            checksum.finish()
+        }
+    }
+
+    /// Number of symbols the checksum trailer takes
+    pub fn trailer_len<B: Bits>() -> usize {
+        4usize.div_ceil(B::group_bytes()) * B::group_len()
+    }
+
+    /// Writes symbols to `writer` as bytes, holding back the last [`trailer_len`]
+    /// ones and checking them against the checksum of the rest at the end
+    pub fn concat_checked<B, E>(
+        bits: impl Iterator<Item = Result<B, ConcatError<E>>>,
+        mut writer: impl std::io::Write,
+        checksum_error: impl FnOnce(Option<u32>, u32) -> E,
+    ) -> Result<(), ConcatError<E>>
+    where
+        B: Bits,
+    {
+        let trailer_len = trailer_len::<B>();
+        let mut held = std::collections::VecDeque::with_capacity(trailer_len + B::group_len());
+        let mut checksum = Checksum::<B>::new();
+        let mut group = Vec::with_capacity(B::group_bytes());
+
+        for b in bits {
+            held.push_back(b?);
+            if held.len() == trailer_len + B::group_len() {
+                group.clear();
+                B::concat(held.drain(..B::group_len()).map(Ok::<_, !>), &mut group)
+                    .expect("writing to a vector should not fail");
+                checksum.update(&group);
+                writer.write_all(&group).map_err(ConcatError::Io)?;
+            }
+        }
+
+        let found = checksum.finish();
+        if held.len() < trailer_len {
+            return Err(ConcatError::Parent(checksum_error(None, found)));
+        }
+
+        let mut trailer = Vec::new();
+        B::concat(held.drain(..).map(Ok::<_, !>), &mut trailer)
+            .expect("writing to a vector should not fail");
+        let expected = u32::from_be_bytes(trailer[..4].try_into().unwrap());
+        if expected != found {
+            return Err(ConcatError::Parent(checksum_error(Some(expected), found)));
+        }
+        Ok(())
+    }
+}
+
+pub use checksum::{Checksum, trailer_len};
+
 mod decoder {
+    use crate::lexing::byte_lexer::ReadError;
     use crate::{encoding, letters::LetterId};
 
     use super::*;
@@ -122,6 +446,22 @@ mod decoder {
     pub enum Error {
         Lexing(lexing::iter::Error<char>),
         Hajiman(lexing::iter::Error<String>),
+        Utf8(Vec<u8>),
+        /// Checksum in the trailer does not match the data, or the trailer is missing
+        ChecksumMismatch {
+            expected: Option<u32>,
+            found: u32,
+        },
+    }
+
+    impl From<lexing::byte_lexer::Error> for Error {
+        fn from(value: lexing::byte_lexer::Error) -> Self {
+            use lexing::byte_lexer::Error::*;
+            match value {
+                Lexing(e) => Error::Lexing(e),
+                Utf8(bytes) => Error::Utf8(bytes),
+            }
+        }
     }
 
     #[derive(Debug, Clone)]
@@ -136,17 +476,26 @@ mod decoder {
         B: Bits,
     {
         pub fn from_encoding(encoding: &JimiEncoding<B>) -> Result<Self, LexemError> {
-            let lexer = StringLexer::new(&encoding.tokens)?;
+            let lexer = StringLexer::with_aliases(&encoding.tokens, encoding.normalization)?;
             Ok(Self {
                 lexer,
                 decoder: encoding.encoding.decoder(),
-                tokens: encoding.tokens.clone(),
+                tokens: encoding.canonical_tokens(),
             })
         }
 
         pub fn map_error(&self, e: lexing::Error<LetterId, lexing::iter::Error<char>>) -> Error {
+            self.map_letter_error(e, Error::Lexing, Error::Hajiman)
+        }
+
+        pub(super) fn map_letter_error<E, E1>(
+            &self,
+            e: lexing::Error<LetterId, E>,
+            f1: impl FnOnce(E) -> E1,
+            f2: impl FnOnce(lexing::iter::Error<String>) -> E1,
+        ) -> E1 {
             e.map(|letter_id| self.tokens[letter_id].clone())
-                .flatten(|le| Error::Lexing(le), |he| Error::Hajiman(he))
+                .flatten(f1, f2)
         }
 
         pub fn decode_chars<It: Iterator<Item = char>>(
@@ -186,15 +535,305 @@ mod decoder {
             Ok(v)
         }
 
+        /// Decode UTF-8 bytes from `reader` as they come, without collecting them
+        /// into a string first
+        pub fn decode_reader(
+            &self,
+            reader: impl std::io::Read,
+            writer: impl std::io::Write,
+        ) -> Result<(), ConcatError<Error>> {
+            B::concat(self.decode_reader_to_bits(reader), writer).map_err(|e| match e {
+                ConcatError::Parent(e) => e,
+                ConcatError::Io(e) => ConcatError::Io(e),
+            })
+        }
+
+        /// Like [`JimiDecoder::decode`], but the input ends with a checksum trailer,
+        /// which is checked instead of being written out
+        pub fn decode_checked<'a, S: AsRef<str> + ?Sized + 'a>(
+            &'a self,
+            s: &'a S,
+            writer: impl std::io::Write,
+        ) -> Result<(), ConcatError<Error>> {
+            checksum::concat_checked(
+                self.decode_to_bits(s)
+                    .map(|b| b.map_err(ConcatError::Parent)),
+                writer,
+                Self::checksum_mismatch,
+            )
+        }
+
+        /// Like [`JimiDecoder::decode_reader`], but the input ends with a checksum
+        /// trailer, which is checked instead of being written out
+        pub fn decode_reader_checked(
+            &self,
+            reader: impl std::io::Read,
+            writer: impl std::io::Write,
+        ) -> Result<(), ConcatError<Error>> {
+            checksum::concat_checked(
+                self.decode_reader_to_bits(reader),
+                writer,
+                Self::checksum_mismatch,
+            )
+        }
+
+        /// Decode each line of `reader` on its own, going on past lines that fail.
+        ///
+        /// Lines which are not valid UTF-8 or honey water come out as errors, so that the
+        /// caller can treat them as erasures.
+        pub fn decode_lines_lenient<'a>(
+            &'a self,
+            reader: impl std::io::BufRead + 'a,
+        ) -> impl Iterator<Item = std::io::Result<Result<Vec<u8>, Error>>> + 'a {
+            reader.split(b'\n').map(move |line| {
+                let mut line = line?;
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                Ok(match String::from_utf8(line) {
+                    Ok(s) => self.decode_to_vec(&s).map_err(|e| match e {
+                        ConcatError::Parent(e) => e,
+                        ConcatError::Io(_) => unreachable!("writing to a vector does not fail"),
+                    }),
+                    Err(e) => Err(Error::Utf8(e.into_bytes())),
+                })
+            })
+        }
+
+        fn decode_reader_to_bits(
+            &self,
+            reader: impl std::io::Read,
+        ) -> impl Iterator<Item = Result<B, ConcatError<Error>>> {
+            self.decoder
+                .decode_from_error(self.lexer.lex_reader(reader))
+                .map(|x| {
+                    x.map_err(|e| {
+                        self.map_letter_error(
+                            e,
+                            |e| match e {
+                                ReadError::Lexing(e) => ConcatError::Parent(e.into()),
+                                ReadError::Io(e) => ConcatError::Io(e),
+                            },
+                            |he| ConcatError::Parent(Error::Hajiman(he)),
+                        )
+                    })
+                })
+        }
+
+        fn checksum_mismatch(expected: Option<u32>, found: u32) -> Error {
+            Error::ChecksumMismatch { expected, found }
+        }
+
         pub fn lexer(&self) -> &StringLexer {
             &self.lexer
         }
+
+        pub fn code_decoder(&self) -> &Decoder<B> {
+            &self.decoder
+        }
     }
 }
 
 pub use decoder::Error as JimiError;
 pub use decoder::JimiDecoder;
 
+mod state {
+    use super::*;
+    use crate::letters::LetterId;
+    use crate::lexing::byte_lexer::{self, ByteLexer};
+
+    /// Decodes honey water pushed piece by piece, for example as it arrives from
+    /// network.
+    ///
+    /// The state owns everything needed to carry on: the encoding, the unfinished
+    /// token, the unfinished code and the symbols not yet making up whole bytes.
+    /// So it can be serialized and resumed later.
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    #[serde(into = "Saved<B>", try_from = "Saved<B>")]
+    pub struct JimiDecodeState<B>
+    where
+        B: Bits,
+    {
+        encoding: JimiEncoding<B>,
+        decoder: JimiDecoder<B>,
+        lexer: byte_lexer::Cursor,
+        code: Vec<LetterId>,
+        bits: Vec<B>,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Saved<B>
+    where
+        B: Bits,
+    {
+        encoding: JimiEncoding<B>,
+        lexer: byte_lexer::Cursor,
+        code: Vec<LetterId>,
+        bits: Vec<B>,
+    }
+
+    impl<B> From<JimiDecodeState<B>> for Saved<B>
+    where
+        B: Bits,
+    {
+        fn from(value: JimiDecodeState<B>) -> Self {
+            Self {
+                encoding: value.encoding,
+                lexer: value.lexer,
+                code: value.code,
+                bits: value.bits,
+            }
+        }
+    }
+
+    impl<B> TryFrom<Saved<B>> for JimiDecodeState<B>
+    where
+        B: Bits,
+    {
+        type Error = String;
+        fn try_from(value: Saved<B>) -> Result<Self, Self::Error> {
+            let decoder = value
+                .encoding
+                .decoder()
+                .map_err(|e| format!("tokens cannot be lexed: {:?}", e))?;
+
+            if ByteLexer::resume(decoder.lexer(), value.lexer.clone()).is_none() {
+                return Err("lexer state does not belong to the tokens".to_string());
+            }
+            if !decoder.code_decoder().is_prefix(&value.code) {
+                return Err("unfinished code does not belong to the encoding".to_string());
+            }
+            if value.bits.len() >= B::group_len() {
+                return Err(format!(
+                    "expected less than {} unfinished symbols, got {}",
+                    B::group_len(),
+                    value.bits.len()
+                ));
+            }
+
+            Ok(Self {
+                encoding: value.encoding,
+                decoder,
+                lexer: value.lexer,
+                code: value.code,
+                bits: value.bits,
+            })
+        }
+    }
+
+    impl<B> JimiDecodeState<B>
+    where
+        B: Bits,
+    {
+        pub fn new(encoding: JimiEncoding<B>) -> Result<Self, LexemError> {
+            Ok(Self {
+                decoder: encoding.decoder()?,
+                encoding,
+                lexer: byte_lexer::Cursor::default(),
+                code: Vec::new(),
+                bits: Vec::new(),
+            })
+        }
+
+        pub fn encoding(&self) -> &JimiEncoding<B> {
+            &self.encoding
+        }
+
+        pub fn push(&mut self, s: &str) -> Result<Vec<u8>, JimiError> {
+            self.push_bytes(s.as_bytes())
+        }
+
+        /// Like [`JimiDecodeState::push`], but the input may be cut anywhere,
+        /// even in the middle of a char
+        pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<u8>, JimiError> {
+            let mut out = Vec::new();
+            self.push_into(bytes, &mut out)?;
+            Ok(out)
+        }
+
+        /// Decode `bytes` into `out`.
+        ///
+        /// On error, `out` keeps what was decoded before the error, and the
+        /// unfinished code is dropped, so that pushing more input resynchronizes
+        /// at the next token.
+        pub fn push_into(&mut self, bytes: &[u8], out: &mut Vec<u8>) -> Result<(), JimiError> {
+            let mut lexer =
+                ByteLexer::resume(self.decoder.lexer(), std::mem::take(&mut self.lexer))
+                    .expect("lexer cursor is checked on construction");
+
+            let r = decode_letters(
+                &self.decoder,
+                &mut self.code,
+                &mut self.bits,
+                lexer.feed(bytes),
+                out,
+            );
+
+            self.lexer = lexer.into_cursor();
+            r
+        }
+
+        /// Decode what is held back till the end of input, and check that input
+        /// does not stop in the middle of a token or a code.
+        ///
+        /// Symbols not making up whole bytes are padding added by the encoder and
+        /// are dropped.
+        pub fn finish(mut self) -> Result<Vec<u8>, JimiError> {
+            let mut lexer = ByteLexer::resume(self.decoder.lexer(), self.lexer)
+                .expect("lexer cursor is checked on construction");
+
+            let mut out = Vec::new();
+            decode_letters(
+                &self.decoder,
+                &mut self.code,
+                &mut self.bits,
+                lexer.flush(),
+                &mut out,
+            )?;
+            lexer.finish()?;
+
+            if self.code.is_empty() {
+                Ok(out)
+            } else {
+                Err(self.decoder.map_letter_error(
+                    lexing::Error::UnexpectedTermination(self.code),
+                    |e: !| e,
+                    JimiError::Hajiman,
+                ))
+            }
+        }
+    }
+
+    fn decode_letters<B>(
+        decoder: &JimiDecoder<B>,
+        code: &mut Vec<LetterId>,
+        bits: &mut Vec<B>,
+        letters: impl Iterator<Item = Result<LetterId, byte_lexer::Error>>,
+        out: &mut Vec<u8>,
+    ) -> Result<(), JimiError>
+    where
+        B: Bits,
+    {
+        for letter in letters {
+            let b = decoder.code_decoder().step(code, letter?).map_err(|e| {
+                code.clear();
+                decoder.map_letter_error(e, |e| e, JimiError::Hajiman)
+            })?;
+
+            if let Some(b) = b {
+                bits.push(b);
+                if bits.len() == B::group_len() {
+                    B::concat(bits.drain(..).map(Ok::<_, !>), &mut *out)
+                        .expect("writing to a vector should not fail");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub use state::JimiDecodeState;
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -202,8 +841,9 @@ mod test {
         Bits,
         bits::{Bits4, Bits6, Bits8},
     };
-    use crate::characters::CharacterFrequency;
+    use crate::characters::{CharacterCounter, CharacterFrequency};
     use crate::hajimi::hajimi_tokens;
+    use crate::jimi::decoder::Error;
 
     fn test_honey_water<B: Bits>() {
         let encoding = JimiEncoding::<B>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
@@ -218,6 +858,296 @@ mod test {
         assert_eq!(src, decoded[..encoded.original_length]);
     }
 
+    fn test_checksum<B: Bits>() {
+        let encoding = JimiEncoding::<B>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let (encoder, decoder) = (encoding.encoder(), encoding.decoder().unwrap());
+        let src: Vec<u8> = (0..255).chain((10..200).rev()).collect();
+
+        let encoded: String = encoder
+            .encode(&src)
+            .data
+            .chain(encoder.encode_checksum(Checksum::<B>::of(&src)))
+            .collect();
+
+        let mut decoded = Vec::new();
+        decoder.decode_checked(&encoded, &mut decoded).unwrap();
+        assert_eq!(src, decoded[..src.len()]);
+        assert!(decoded[src.len()..].iter().all(|&b| b == 0));
+
+        let mut decoded = Vec::new();
+        decoder
+            .decode_reader_checked(encoded.as_bytes(), &mut decoded)
+            .unwrap();
+        assert_eq!(src, decoded[..src.len()]);
+
+        let tampered = encoded.replacen("曼波", "哇恰", 1);
+        assert!(matches!(
+            decoder.decode_checked(&tampered, &mut Vec::new()),
+            Err(ConcatError::Parent(Error::ChecksumMismatch {
+                expected: Some(_),
+                ..
+            }))
+        ));
+
+        let short: String = encoder.encode_checksum(0).take(1).collect();
+        assert!(matches!(
+            decoder.decode_checked(&short, &mut Vec::new()),
+            Err(ConcatError::Parent(Error::ChecksumMismatch {
+                expected: None,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_checksum_8bit() {
+        test_checksum::<Bits8>();
+    }
+
+    #[test]
+    fn test_checksum_6bit() {
+        test_checksum::<Bits6>();
+    }
+
+    #[test]
+    fn test_checksum_4bit() {
+        test_checksum::<Bits4>();
+    }
+
+    fn test_skewed_frequency<B: Bits>() {
+        // Codes of rare symbols used to overlap, as their shares of the frequency
+        // round to the same float
+        let src = vec![0u8; 3000];
+        let mut counter = CharacterCounter::<B>::empty();
+        counter.count(B::iter_bytes(&src).data);
+        let freq = counter.finish();
+        for set in TokenSet::all() {
+            let encoding = JimiEncoding::<B>::from_token_set(set, &freq);
+            let (encoder, decoder) = (encoding.encoder(), encoding.decoder().unwrap());
+            let encoded: String = encoder.encode(&src).data.collect();
+            let mut decoded = Vec::new();
+            decoder.decode(&encoded, &mut decoded).unwrap();
+            assert_eq!(src, decoded[..src.len()], "{} {}", set.name(), B::N);
+        }
+    }
+
+    #[test]
+    fn test_skewed_frequency_all_widths() {
+        test_skewed_frequency::<Bits8>();
+        test_skewed_frequency::<Bits6>();
+        test_skewed_frequency::<Bits4>();
+    }
+
+    #[test]
+    fn test_decode_reader() {
+        let encoding =
+            JimiEncoding::<Bits6>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let (encoder, decoder) = (encoding.encoder(), encoding.decoder().unwrap());
+        let src: Vec<u8> = (0..255).collect();
+
+        let encoded: String = encoder.encode(&src).data.collect();
+
+        let mut decoded = Vec::new();
+        decoder
+            .decode_reader(encoded.as_bytes(), &mut decoded)
+            .unwrap();
+        assert_eq!(src, decoded[..src.len()]);
+
+        let truncated = &encoded.as_bytes()[..encoded.len() - 1];
+        assert!(matches!(
+            decoder.decode_reader(truncated, &mut Vec::new()),
+            Err(ConcatError::Parent(Error::Utf8(..)))
+        ));
+    }
+
+    fn test_decode_state<B: Bits + serde::Serialize + serde::de::DeserializeOwned>() {
+        let encoding = JimiEncoding::<B>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let src: Vec<u8> = (0..255).chain((10..200).rev()).collect();
+        let encoded: String = encoding.encoder().encode(&src).data.collect();
+        let encoded = encoded.as_bytes();
+
+        for chunk in [1, 2, 5, 13, 64] {
+            let mut state = encoding.decode_state().unwrap();
+            let mut decoded = Vec::new();
+            for (i, piece) in encoded.chunks(chunk).enumerate() {
+                decoded.extend(state.push_bytes(piece).unwrap());
+                if i % 97 == 0 {
+                    let saved = serde_json::to_string(&state).unwrap();
+                    state = serde_json::from_str(&saved).unwrap();
+                }
+            }
+            decoded.extend(state.finish().unwrap());
+
+            assert_eq!(src, decoded[..src.len()]);
+        }
+    }
+
+    #[test]
+    fn test_decode_state_8bit() {
+        test_decode_state::<Bits8>();
+    }
+
+    #[test]
+    fn test_decode_state_6bit() {
+        test_decode_state::<Bits6>();
+    }
+
+    #[test]
+    fn test_decode_state_unfinished() {
+        let encoding =
+            JimiEncoding::<Bits8>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+        let mut state = encoding.decode_state().unwrap();
+
+        state.push("哈基").unwrap();
+        assert!(matches!(
+            state.finish(),
+            Err(Error::Lexing(lexing::Error::UnexpectedTermination(..)))
+        ));
+    }
+
+    #[test]
+    fn test_normalized_tokens() {
+        use crate::lexing::normalize::NormalForm;
+
+        let tokens = LetterIdIndexed::new(
+            [
+                "hajimi",
+                "nabeiluduo",
+                "axiga",
+                "haiyaku",
+                "oumaziri",
+                "manbo",
+                "dagoujiao",
+                "dingdongji",
+                "wuo",
+                "waqia",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        );
+        let encoding = JimiEncoding::<Bits8>::new(tokens, &CharacterFrequency::all_equal())
+            .with_normalization(Normalization {
+                form: Some(NormalForm::Nfkc),
+                case_fold: true,
+            });
+        let src = b"honey water";
+        let encoded: String = encoding.encoder().encode(src).data.collect();
+
+        let shouted = encoded
+            .replace("hajimi", "HaJiMi")
+            .replace("manbo", "ＭＡＮＢＯ");
+        let decoder = encoding.decoder().unwrap();
+        assert_eq!(decoder.decode_to_vec(&shouted).unwrap(), src);
+
+        let mut state = encoding.decode_state().unwrap();
+        let mut decoded = Vec::new();
+        for piece in shouted.as_bytes().chunks(5) {
+            decoded.extend(state.push_bytes(piece).unwrap());
+        }
+        decoded.extend(state.finish().unwrap());
+        assert_eq!(decoded, src);
+    }
+
+    #[test]
+    fn test_normalized_tokens_collision() {
+        let tokens = LetterIdIndexed::new(vec!["Manbo".to_string(), "manbo".to_string()]);
+        let normalization = Normalization {
+            form: None,
+            case_fold: true,
+        };
+        assert!(matches!(
+            StringLexer::with_normalization(&tokens, normalization),
+            Err(LexemError::Collision(..))
+        ));
+    }
+
+    #[test]
+    fn test_token_aliases() {
+        let mut tokens = hajimi_tokens().map(|_, s| vec![s]);
+        tokens.first_mut().unwrap().push("哈基咪".to_string());
+        tokens.last_mut().unwrap().push("哇掐".to_string());
+
+        let encoding =
+            JimiEncoding::<Bits8>::with_aliases(tokens, &CharacterFrequency::all_equal());
+        let src: Vec<u8> = (0..255).collect();
+        let encoded: String = encoding.encoder().encode(&src).data.collect();
+        assert!(!encoded.contains("哈基咪") && !encoded.contains("哇掐"));
+
+        let aliased = encoded.replace("哈基米", "哈基咪").replace("哇恰", "哇掐");
+        let decoder = encoding.decoder().unwrap();
+        assert_eq!(decoder.decode_to_vec(&aliased).unwrap(), src);
+    }
+
+    #[test]
+    fn test_non_prefix_free_aliases() {
+        let mut tokens = hajimi_tokens().map(|_, s| vec![s]);
+        tokens.last_mut().unwrap().push("哈基".to_string());
+
+        let encoding =
+            JimiEncoding::<Bits8>::with_aliases(tokens, &CharacterFrequency::all_equal());
+        assert!(matches!(encoding.decoder(), Err(LexemError::NonPrefixFree)));
+    }
+
+    #[test]
+    fn test_deserialize_tokens_without_aliases() {
+        let encoding =
+            JimiEncoding::<Bits8>::new(hajimi_tokens(), &CharacterFrequency::all_equal());
+
+        let mut json = serde_json::to_value(&encoding).unwrap();
+        json["tokens"] = serde_json::to_value(hajimi_tokens()).unwrap();
+
+        let parsed: JimiEncoding<Bits8> = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, encoding);
+    }
+
+    #[test]
+    fn test_serialize_named_token_set() {
+        let set = TokenSet::by_name("hajimi").unwrap();
+        let encoding = JimiEncoding::<Bits8>::from_token_set(set, &CharacterFrequency::all_equal());
+
+        let json = serde_json::to_value(&encoding).unwrap();
+        assert_eq!(json["tokens"], "hajimi");
+
+        let parsed: JimiEncoding<Bits8> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(parsed, encoding);
+        assert_eq!(parsed.tokens(), &set.tokens());
+
+        let mut unknown = json;
+        unknown["tokens"] = "nope".into();
+        assert!(serde_json::from_value::<JimiEncoding<Bits8>>(unknown).is_err());
+    }
+
+    #[test]
+    fn test_keyed_encoding() {
+        let src = b"keyed honey water, keyed honey water";
+        let mut counter = CharacterCounter::<Bits8>::empty();
+        counter.count(Bits8::iter_bytes(src).data);
+        let plain = JimiEncoding::new(hajimi_tokens(), &counter.finish());
+        let encoded_len =
+            |encoding: &JimiEncoding<Bits8>| encoding.encoder().encode(src).data.count();
+
+        let key = Key::new("passphrase");
+        let shuffled =
+            CharacterCounter::<Bits8>::from_counts(&key.shuffle_counts::<Bits8>(&counter.counts()))
+                .unwrap();
+        let base = JimiEncoding::new(hajimi_tokens(), &shuffled.finish());
+        for shuffle_tokens in [false, true] {
+            let keyed = base.keyed(&key, shuffle_tokens);
+            assert_ne!(keyed, plain);
+            assert_eq!(keyed, base.keyed(&Key::new("passphrase"), shuffle_tokens));
+            assert_eq!(encoded_len(&keyed), encoded_len(&plain));
+
+            let encoded: String = keyed.encoder().encode(src).data.collect();
+            let decoded = keyed.decoder().unwrap().decode_to_vec(&encoded).unwrap();
+            assert_eq!(decoded, src);
+
+            let wrong = base.keyed(&Key::new("wrong"), shuffle_tokens);
+            let decoded = wrong.decoder().unwrap().decode_to_vec(&encoded);
+            assert!(decoded.is_err() || decoded.unwrap() != src);
+        }
+    }
+
     #[test]
     fn test_honey_water_8bit() {
         test_honey_water::<Bits8>();
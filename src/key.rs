@@ -0,0 +1,151 @@
+use sha2::{Digest, Sha256};
+
+use crate::bits_key::{Bits, BitsIter, BitsMap};
+use crate::letters::{LetterId, LetterIdIndexed};
+
+/// Secret deciding which symbol gets which code, derived from a passphrase.
+///
+/// Only a hash of the passphrase is kept. Keys are never written into headers or
+/// encodings, so honey water encoded with one cannot be decoded without it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key {
+    seed: [u8; 32],
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl Key {
+    pub fn new(passphrase: &str) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(b"hajiman key\0");
+        hasher.update(passphrase.as_bytes());
+        Self {
+            seed: hasher.finalize().into(),
+        }
+    }
+
+    /// Which symbol's code each symbol is given
+    pub fn symbols<B: Bits>(&self) -> BitsMap<B, B> {
+        let all: Vec<B> = BitsIter::begin_zero().collect();
+        let shuffle = self.shuffle("symbols", all.len());
+        BitsMap::new(B::zero()).map(|b: B, _| all[shuffle[b.to_usize()]].clone())
+    }
+
+    /// Which letter each letter is renamed to, only ever among letters of the same
+    /// cost so that encodings stay as compact
+    pub fn letters(&self, costs: &LetterIdIndexed<usize>) -> LetterIdIndexed<LetterId> {
+        let mut renamed = costs.map_by_ref(|id, _| id);
+        let mut by_cost: Vec<(usize, LetterId)> =
+            costs.iter_with_id().map(|(id, &cost)| (cost, id)).collect();
+        by_cost.sort();
+
+        for class in by_cost.chunk_by(|a, b| a.0 == b.0) {
+            let shuffle = self.shuffle(&format!("letters {}", class[0].0), class.len());
+            for (i, &j) in shuffle.iter().enumerate() {
+                renamed[class[i].1] = class[j].1;
+            }
+        }
+        renamed
+    }
+
+    /// Counts to build an encoding from so that, once keyed, it is as compact for data
+    /// with symbol `counts` as the encoding built from `counts` itself.
+    ///
+    /// Keyed symbols take the codes of others, so the counts have to move along.
+    pub fn shuffle_counts<B: Bits>(&self, counts: &[usize]) -> Vec<usize> {
+        let mut shuffled = vec![0; counts.len()];
+        for (b, to) in self.symbols::<B>().iter() {
+            shuffled[to.clone().to_usize()] = counts[b.to_usize()];
+        }
+        shuffled
+    }
+
+    /// Fisher–Yates shuffle of `0..n`, the same for the same key and `purpose`
+    fn shuffle(&self, purpose: &str, n: usize) -> Vec<usize> {
+        let mut stream = KeyStream::new(self, purpose);
+        let mut v: Vec<usize> = (0..n).collect();
+        for i in (1..n).rev() {
+            let j = stream.below(i as u64 + 1) as usize;
+            v.swap(i, j);
+        }
+        v
+    }
+}
+
+/// SHA-256 of the seed, purpose and a counter, as a stream of numbers
+struct KeyStream<'a> {
+    key: &'a Key,
+    purpose: &'a str,
+    counter: u64,
+    block: [u8; 32],
+    pos: usize,
+}
+
+impl<'a> KeyStream<'a> {
+    fn new(key: &'a Key, purpose: &'a str) -> Self {
+        Self {
+            key,
+            purpose,
+            counter: 0,
+            block: [0; 32],
+            pos: 32,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if self.pos == self.block.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(self.key.seed);
+            hasher.update(self.purpose.as_bytes());
+            hasher.update(self.counter.to_be_bytes());
+            self.block = hasher.finalize().into();
+            self.counter += 1;
+            self.pos = 0;
+        }
+        let x = u64::from_be_bytes(self.block[self.pos..self.pos + 8].try_into().unwrap());
+        self.pos += 8;
+        x
+    }
+
+    /// Uniformly random number below `n`
+    fn below(&mut self, n: u64) -> u64 {
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bits::Bits8;
+
+    #[test]
+    fn test_key_permutations() {
+        let key = Key::new("honey");
+        let symbols = key.symbols::<Bits8>();
+        assert_eq!(symbols, Key::new("honey").symbols::<Bits8>());
+        assert_ne!(symbols, Key::new("water").symbols::<Bits8>());
+
+        let mut seen: Vec<usize> = symbols.iter().map(|(_, &b)| b.to_usize()).collect();
+        seen.sort();
+        assert_eq!(seen, (0..256).collect::<Vec<_>>());
+
+        let costs = LetterIdIndexed::new(vec![3, 2, 3, 3, 1, 2]);
+        let letters = key.letters(&costs);
+        for (id, &to) in letters.iter_with_id() {
+            assert_eq!(costs[id], costs[to]);
+        }
+        let mut seen: Vec<usize> = letters.iter().map(|id| id.index()).collect();
+        seen.sort();
+        assert_eq!(seen, (0..6).collect::<Vec<_>>());
+    }
+}
@@ -50,6 +50,30 @@ impl Polynomial {
     pub fn coef_mut(&mut self, deg: usize) -> &mut isize {
         &mut self.coefs[deg]
     }
+
+    pub fn eval(&self, x: f64) -> f64 {
+        self.coefs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, c| acc * x + *c as f64)
+    }
+
+    /// Find a root in `[lo, hi]` by bisection, given the sign changes between the ends
+    pub fn root_between(&self, mut lo: f64, mut hi: f64) -> Option<f32> {
+        let lo_sign = self.eval(lo).signum();
+        if lo_sign == self.eval(hi).signum() {
+            return None;
+        }
+        for _ in 0..64 {
+            let mid = (lo + hi) / 2.0;
+            if self.eval(mid).signum() == lo_sign {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        Some(((lo + hi) / 2.0) as f32)
+    }
 }
 
 #[derive(Debug)]
@@ -75,10 +99,14 @@ impl LetterCosts {
         });
         *poly.coef_mut(0) -= 1;
 
-        let c = *poly
-            .positive_roots()
-            .first()
-            .ok_or(SolveCharacteristicsEquationFail)?;
+        // Sturm sequences lose roots to rounding for some small costs; since all
+        // costs are positive, the polynomial increases on (0, 1] and bisection is safe.
+        let c = match poly.positive_roots().first() {
+            Some(c) => *c,
+            None => poly
+                .root_between(0.0, 1.0)
+                .ok_or(SolveCharacteristicsEquationFail)?,
+        };
 
         let r = LetterCosts { costs, c };
         r.check();
@@ -114,6 +142,10 @@ pub type Code = lexing::Code<LetterId>;
 pub struct LetterId(usize);
 
 impl LetterId {
+    pub fn index(self) -> usize {
+        self.0
+    }
+
     pub fn before(self) -> impl Iterator<Item = LetterId> + Clone {
         (0..self.0).map(|x| LetterId(x))
     }
@@ -170,6 +202,10 @@ impl<T> LetterIdIndexed<T> {
         )
     }
 
+    pub fn get(&self, id: LetterId) -> Option<&T> {
+        self.0.get(id.0)
+    }
+
     pub fn first(&self) -> Option<&T> {
         self.0.first()
     }
@@ -228,4 +264,11 @@ pub mod test {
     fn test_build_letters() {
         let _ = example_letters();
     }
+
+    #[test]
+    fn test_build_letters_short_costs() {
+        let costs = LetterIdIndexed::new(vec![3, 4, 3, 3, 4, 2, 3, 3, 2, 2]);
+        let letters = LetterCosts::build(costs).unwrap();
+        assert!((letters.c() - 0.4265).abs() < 1e-3);
+    }
 }
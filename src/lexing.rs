@@ -1,6 +1,8 @@
 use std::{marker::PhantomData, ops::Deref};
 
+mod dfa;
 mod map;
+pub mod normalize;
 pub use map::Map;
 
 pub trait Lexer {
@@ -78,6 +80,8 @@ pub enum Layer<I, L> {
 pub enum LexemError {
     NonPrefixFree,
     EmptyLexem,
+    /// Two tokens become the same after normalization
+    Collision(String, String),
 }
 
 impl<I, L> Layer<I, L> {
@@ -147,7 +151,7 @@ where
     Ok(tree_layer)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error<W, E> {
     UnexpectedTermination(Vec<W>),
     Unexpected(Vec<W>, W),
@@ -342,3 +346,6 @@ pub mod iter {
 
 pub mod string_lexer;
 pub use string_lexer::StringLexer;
+
+pub mod byte_lexer;
+pub use byte_lexer::ByteLexer;
@@ -0,0 +1,519 @@
+use std::io::Read;
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+use super::dfa::{self, StateId, Step};
+use super::normalize::Normalizer;
+use super::{StringLexer, iter};
+use crate::letters::LetterId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Bytes are valid UTF-8, but the chars do not form a token
+    Lexing(iter::Error<char>),
+    /// Bytes do not form a token, and are not valid UTF-8 either
+    Utf8(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Lexing(Error),
+    Io(std::io::Error),
+}
+
+/// A rejected char whose bytes are split across chunks
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+struct Rejecting {
+    prefix: Vec<char>,
+    partial: Vec<u8>,
+}
+
+/// Where a [`ByteLexer`] stopped, detached from the [`StringLexer`] it runs on,
+/// so that lexing can be resumed later
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Cursor {
+    state: StateId,
+    rejecting: Option<Rejecting>,
+    /// Bytes of a char split across chunks, when chars are normalized
+    undecoded: Vec<u8>,
+    normalizer: Normalizer,
+    /// Normalized chars not yet fed to the automaton
+    ready: VecDeque<char>,
+}
+
+/// Lexes UTF-8 bytes fed in arbitrary chunks, without decoding them to chars.
+///
+/// Match state is kept across chunks, so a token may be split anywhere, even
+/// in the middle of a char. Bytes are only decoded as UTF-8 when they fail to
+/// form a token, to report what went wrong, or when the [`StringLexer`]
+/// normalizes its input.
+#[derive(Debug, Clone)]
+pub struct ByteLexer<'t> {
+    lexer: &'t StringLexer,
+    cursor: Cursor,
+}
+
+enum FirstChar {
+    Char(char),
+    /// Length of the invalid sequence
+    Invalid(usize),
+    Incomplete,
+}
+
+fn first_char(bytes: &[u8]) -> FirstChar {
+    let head = &bytes[..bytes.len().min(4)];
+    let valid = match str::from_utf8(head) {
+        Ok(s) => s,
+        Err(e) if e.valid_up_to() > 0 => str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+        Err(e) => {
+            return match e.error_len() {
+                Some(n) => FirstChar::Invalid(n),
+                None => FirstChar::Incomplete,
+            };
+        }
+    };
+    match valid.chars().next() {
+        Some(c) => FirstChar::Char(c),
+        None => FirstChar::Incomplete,
+    }
+}
+
+/// Split `bytes` at the last char boundary
+fn split_at_boundary(bytes: &[u8]) -> (Vec<char>, &[u8]) {
+    let boundary = match str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        Err(e) => e.valid_up_to(),
+    };
+    let chars = str::from_utf8(&bytes[..boundary])
+        .unwrap()
+        .chars()
+        .collect();
+    (chars, &bytes[boundary..])
+}
+
+impl<'t> ByteLexer<'t> {
+    pub fn new(lexer: &'t StringLexer) -> Self {
+        Self {
+            lexer,
+            cursor: Cursor::default(),
+        }
+    }
+
+    /// Continue from `cursor`, or `None` if `cursor` does not come from `lexer`
+    pub fn resume(lexer: &'t StringLexer, cursor: Cursor) -> Option<Self> {
+        let valid = (cursor.state as usize) < lexer.dfa().n_states()
+            && cursor
+                .rejecting
+                .as_ref()
+                .is_none_or(|r| r.partial.len() < 4)
+            && cursor.undecoded.len() < 4;
+        valid.then_some(Self { lexer, cursor })
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn into_cursor(self) -> Cursor {
+        self.cursor
+    }
+
+    /// Lex `bytes`, continuing from where the last chunk stopped
+    pub fn feed<'a>(&'a mut self, bytes: &'a [u8]) -> Feed<'a, 't> {
+        Feed { lexer: self, bytes }
+    }
+
+    /// Mark the end of input, lexing chars held back because they might still
+    /// combine with chars to come.
+    ///
+    /// Only does something if the [`StringLexer`] normalizes its input.
+    pub fn flush(&mut self) -> Feed<'_, 't> {
+        let normalization = *self.lexer.normalization();
+        self.cursor
+            .normalizer
+            .finish(&normalization, &mut self.cursor.ready);
+        self.feed(&[])
+    }
+
+    /// Whether no token or char is left unfinished
+    pub fn is_clean(&self) -> bool {
+        self.cursor.state == dfa::START
+            && self.cursor.rejecting.is_none()
+            && self.cursor.undecoded.is_empty()
+            && self.cursor.normalizer.is_empty()
+            && self.cursor.ready.is_empty()
+    }
+
+    /// Report the token or char left unfinished by the last chunk, if any.
+    ///
+    /// [`ByteLexer::flush`] should be drained before.
+    pub fn finish(self) -> Result<(), Error> {
+        if let Some(rejecting) = self.cursor.rejecting {
+            return Err(Error::Utf8(rejecting.partial));
+        }
+        if !self.cursor.undecoded.is_empty() {
+            return Err(Error::Utf8(self.cursor.undecoded));
+        }
+        if self.cursor.state == dfa::START
+            && self.cursor.normalizer.is_empty()
+            && self.cursor.ready.is_empty()
+        {
+            return Ok(());
+        }
+
+        let (mut prefix, partial) = split_at_boundary(self.lexer.dfa().prefix(self.cursor.state));
+        prefix.extend(self.cursor.ready);
+        if partial.is_empty() {
+            Err(Error::Lexing(iter::Error::UnexpectedTermination(prefix)))
+        } else {
+            Err(Error::Utf8(partial.to_vec()))
+        }
+    }
+
+    /// Lex from `bytes` until a token is matched or an error is found,
+    /// advancing `bytes` past what has been consumed
+    fn next_from(&mut self, bytes: &mut &[u8]) -> Option<Result<LetterId, Error>> {
+        if !self.lexer.normalization().is_none() {
+            return self.next_normalized(bytes);
+        }
+
+        if let Some(Rejecting { prefix, partial }) = self.cursor.rejecting.take() {
+            let unit = [&partial[..], &bytes[..bytes.len().min(4)]].concat();
+            return self.reject(prefix, &unit, partial.len(), bytes);
+        }
+
+        let dfa = self.lexer.dfa();
+        for (i, &b) in bytes.iter().enumerate() {
+            match dfa.step(self.cursor.state, b) {
+                Step::Goto(s) => self.cursor.state = s,
+                Step::Accept(l) => {
+                    self.cursor.state = dfa::START;
+                    *bytes = &bytes[i + 1..];
+                    return Some(Ok(l));
+                }
+                Step::Dead => {
+                    let (prefix, partial) = split_at_boundary(dfa.prefix(self.cursor.state));
+                    self.cursor.state = dfa::START;
+                    *bytes = &bytes[i..];
+                    let unit = [partial, &bytes[..bytes.len().min(4)]].concat();
+                    return self.reject(prefix, &unit, partial.len(), bytes);
+                }
+            }
+        }
+
+        *bytes = &[];
+        None
+    }
+
+    /// Like [`ByteLexer::next_from`], but chars are decoded and normalized
+    /// before being fed to the automaton
+    fn next_normalized(&mut self, bytes: &mut &[u8]) -> Option<Result<LetterId, Error>> {
+        let normalization = *self.lexer.normalization();
+        loop {
+            while let Some(c) = self.cursor.ready.pop_front() {
+                if let Some(r) = self.lexer.feed_char(&mut self.cursor.state, c) {
+                    return Some(r.map_err(Error::Lexing));
+                }
+            }
+            if bytes.is_empty() {
+                return None;
+            }
+
+            let n_partial = self.cursor.undecoded.len();
+            let unit = if n_partial == 0 {
+                Cow::Borrowed(*bytes)
+            } else {
+                Cow::Owned([&self.cursor.undecoded[..], &bytes[..bytes.len().min(4)]].concat())
+            };
+
+            let (error, len) = match first_char(&unit) {
+                FirstChar::Char(c) => {
+                    self.cursor
+                        .normalizer
+                        .push(&normalization, c, &mut self.cursor.ready);
+                    (None, c.len_utf8())
+                }
+                FirstChar::Invalid(len) => (Some(Error::Utf8(unit[..len].to_vec())), len),
+                FirstChar::Incomplete => {
+                    self.cursor.undecoded = unit.into_owned();
+                    *bytes = &[];
+                    return None;
+                }
+            };
+
+            self.cursor.undecoded.clear();
+            *bytes = &bytes[len.saturating_sub(n_partial).min(bytes.len())..];
+            if let Some(error) = error {
+                return Some(Err(error));
+            }
+        }
+    }
+
+    /// Report the char beginning `unit`, of which the first `n_partial` bytes
+    /// come from earlier chunks and the rest from `bytes`
+    fn reject(
+        &mut self,
+        prefix: Vec<char>,
+        unit: &[u8],
+        n_partial: usize,
+        bytes: &mut &[u8],
+    ) -> Option<Result<LetterId, Error>> {
+        let (error, len) = match first_char(unit) {
+            FirstChar::Char(c) => (
+                Error::Lexing(self.lexer.rejected_after(prefix, c)),
+                c.len_utf8(),
+            ),
+            FirstChar::Invalid(len) => (Error::Utf8(unit[..len].to_vec()), len),
+            FirstChar::Incomplete => {
+                self.cursor.rejecting = Some(Rejecting {
+                    prefix,
+                    partial: unit.to_vec(),
+                });
+                *bytes = &[];
+                return None;
+            }
+        };
+
+        *bytes = &bytes[len.saturating_sub(n_partial).min(bytes.len())..];
+        Some(Err(error))
+    }
+}
+
+pub struct Feed<'a, 't> {
+    lexer: &'a mut ByteLexer<'t>,
+    bytes: &'a [u8],
+}
+
+impl<'a, 't> Iterator for Feed<'a, 't> {
+    type Item = Result<LetterId, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lexer.next_from(&mut self.bytes)
+    }
+}
+
+/// Lexes all bytes read from `R`, reporting unfinished tokens at the end
+pub struct ReaderIter<'t, R> {
+    lexer: Option<ByteLexer<'t>>,
+    reader: R,
+    buf: Vec<u8>,
+    begin: usize,
+    end: usize,
+}
+
+impl<'t, R> ReaderIter<'t, R> {
+    pub fn new(lexer: ByteLexer<'t>, reader: R) -> Self {
+        Self {
+            lexer: Some(lexer),
+            reader,
+            buf: vec![0; 8192],
+            begin: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<'t, R> Iterator for ReaderIter<'t, R>
+where
+    R: Read,
+{
+    type Item = Result<LetterId, ReadError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let lexer = self.lexer.as_mut()?;
+
+            let mut rest = &self.buf[self.begin..self.end];
+            if let Some(r) = lexer.next_from(&mut rest) {
+                self.begin = self.end - rest.len();
+                return Some(r.map_err(ReadError::Lexing));
+            }
+
+            match self.reader.read(&mut self.buf) {
+                Ok(0) => {
+                    if let Some(r) = lexer.flush().next() {
+                        return Some(r.map_err(ReadError::Lexing));
+                    }
+                    let lexer = self.lexer.take()?;
+                    return lexer.finish().err().map(|e| Err(ReadError::Lexing(e)));
+                }
+                Ok(n) => {
+                    self.begin = 0;
+                    self.end = n;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(ReadError::Io(e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Lexer;
+    use super::super::normalize::Normalization;
+    use super::*;
+    use crate::letters::LetterIdIndexed;
+
+    fn test_lexer() -> StringLexer {
+        StringLexer::new(&LetterIdIndexed::new(vec![
+            "哈基米".to_string(),
+            "曼波".to_string(),
+            "ab".to_string(),
+        ]))
+        .unwrap()
+    }
+
+    fn lex_chunks(
+        lexer: &StringLexer,
+        chunks: &[&[u8]],
+    ) -> (Vec<Result<usize, Error>>, Result<(), Error>) {
+        let ids = LetterIdIndexed::repeat((), 3);
+        let index = |l: LetterId| ids.iter_id().position(|id| id == l).unwrap();
+
+        let mut byte_lexer = lexer.byte_lexer();
+        let mut r = Vec::new();
+        for chunk in chunks {
+            r.extend(byte_lexer.feed(chunk).map(|x| x.map(index)));
+        }
+        (r, byte_lexer.finish())
+    }
+
+    #[test]
+    fn test_tokens_split_across_chunks() {
+        let lexer = test_lexer();
+        let bytes = "哈基米ab曼波哈基米".as_bytes();
+
+        for split in 0..bytes.len() {
+            let (r, finish) = lex_chunks(&lexer, &[&bytes[..split], &bytes[split..]]);
+            assert_eq!(r, vec![Ok(0), Ok(2), Ok(1), Ok(0)]);
+            assert_eq!(finish, Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_unexpected_char() {
+        let lexer = test_lexer();
+        let bytes = "哈基曼波ab".as_bytes();
+
+        for split in 0..bytes.len() {
+            let (r, finish) = lex_chunks(&lexer, &[&bytes[..split], &bytes[split..]]);
+            assert_eq!(
+                r,
+                vec![
+                    Err(Error::Lexing(iter::Error::Unexpected(
+                        vec!['哈', '基'],
+                        '曼'
+                    ))),
+                    Err(Error::Lexing(iter::Error::Unexpected(vec![], '波'))),
+                    Ok(2)
+                ]
+            );
+            assert_eq!(finish, Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_unexpected_char_normalized() {
+        let tokens = ["哈基米", "曼波", "ab"].map(String::from).to_vec();
+        let normalization = Normalization {
+            form: None,
+            case_fold: true,
+        };
+        let normalized =
+            StringLexer::with_normalization(&LetterIdIndexed::new(tokens), normalization).unwrap();
+        let lexer = test_lexer();
+        let input = "哈基曼波哈ab米ab";
+
+        let ids = LetterIdIndexed::repeat((), 3);
+        let index = |l: LetterId| ids.iter_id().position(|id| id == l).unwrap();
+        let (raw, _) = lex_chunks(&lexer, &[input.as_bytes()]);
+        let mut byte_lexer = normalized.byte_lexer();
+        let mut r: Vec<_> = byte_lexer.feed(input.as_bytes()).collect();
+        r.extend(byte_lexer.flush());
+        assert_eq!(r.into_iter().map(|x| x.map(index)).collect::<Vec<_>>(), raw);
+
+        for lexer in [&lexer, &normalized] {
+            let r: Vec<_> = lexer
+                .lex_from_error(input.chars().map(Ok::<_, !>))
+                .map(|x| x.map(index).map_err(Error::Lexing))
+                .collect();
+            assert_eq!(r, raw);
+        }
+        assert_eq!(
+            raw,
+            vec![
+                Err(Error::Lexing(iter::Error::Unexpected(
+                    vec!['哈', '基'],
+                    '曼'
+                ))),
+                Err(Error::Lexing(iter::Error::Unexpected(vec![], '波'))),
+                Err(Error::Lexing(iter::Error::Unexpected(vec!['哈'], 'a'))),
+                Err(Error::Lexing(iter::Error::Unexpected(vec![], 'b'))),
+                Err(Error::Lexing(iter::Error::Unexpected(vec![], '米'))),
+                Ok(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_char() {
+        let lexer = test_lexer();
+        let (r, _) = lex_chunks(&lexer, &["a哇b".as_bytes()]);
+        assert_eq!(
+            r,
+            vec![
+                Err(Error::Lexing(iter::Error::Invalid('哇'))),
+                Err(Error::Lexing(iter::Error::Unexpected(vec![], 'b'))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let lexer = test_lexer();
+        let mut bytes = "曼".as_bytes().to_vec();
+        bytes.push(0xff);
+        bytes.extend_from_slice("波".as_bytes());
+
+        let (r, finish) = lex_chunks(&lexer, &[&bytes]);
+        assert_eq!(
+            r,
+            vec![
+                Err(Error::Utf8(vec![0xff])),
+                Err(Error::Lexing(iter::Error::Unexpected(vec![], '波'))),
+            ]
+        );
+        assert_eq!(finish, Ok(()));
+    }
+
+    #[test]
+    fn test_unexpected_termination() {
+        let lexer = test_lexer();
+        let bytes = "曼波哈基".as_bytes();
+
+        let (r, finish) = lex_chunks(&lexer, &[bytes]);
+        assert_eq!(r, vec![Ok(1)]);
+        assert_eq!(
+            finish,
+            Err(Error::Lexing(iter::Error::UnexpectedTermination(vec![
+                '哈', '基'
+            ])))
+        );
+
+        let (_, finish) = lex_chunks(&lexer, &[&bytes[..bytes.len() - 1]]);
+        assert_eq!(finish, Err(Error::Utf8(bytes[9..11].to_vec())));
+    }
+
+    #[test]
+    fn test_lex_reader() {
+        let lexer = test_lexer();
+        let r = lexer
+            .lex_reader("ab曼波哈".as_bytes())
+            .map(|x| x.map_err(|e| format!("{e:?}")))
+            .collect::<Vec<_>>();
+
+        assert_eq!(r.len(), 3);
+        assert!(r[0].is_ok() && r[1].is_ok());
+        assert!(r[2].is_err());
+    }
+}
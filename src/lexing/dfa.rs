@@ -0,0 +1,149 @@
+use super::LexemError;
+
+pub type StateId = u32;
+
+pub const START: StateId = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<L> {
+    Dead,
+    Goto(StateId),
+    Accept(L),
+}
+
+/// A prefix-free token trie flattened into a transition table indexed by
+/// `state * n_classes + class`.
+///
+/// Bytes are first mapped to equivalence classes, so that the table only
+/// spans the bytes that actually occur in some token. Class 0 is shared by
+/// all other bytes and always leads to [`Step::Dead`].
+#[derive(Debug, Clone)]
+pub struct Dfa<L> {
+    classes: [u16; 256],
+    n_classes: usize,
+    table: Vec<Step<L>>,
+    /// Bytes consumed from [`START`] to reach each state
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl<L> Dfa<L>
+where
+    L: Copy,
+{
+    pub fn build<'a>(tokens: impl Iterator<Item = (L, &'a [u8])>) -> Result<Self, LexemError> {
+        let tokens = tokens.collect::<Vec<_>>();
+
+        let mut classes = [0u16; 256];
+        let mut n_classes = 1;
+        for (_, token) in tokens.iter() {
+            for &b in token.iter() {
+                if classes[b as usize] == 0 {
+                    classes[b as usize] = n_classes;
+                    n_classes += 1;
+                }
+            }
+        }
+
+        let mut dfa = Self {
+            classes,
+            n_classes: n_classes as usize,
+            table: Vec::new(),
+            prefixes: Vec::new(),
+        };
+        dfa.add_state(Vec::new());
+
+        for (l, token) in tokens {
+            let Some((&last, init)) = token.split_last() else {
+                return Err(LexemError::EmptyLexem);
+            };
+
+            let mut state = START;
+            for (i, &b) in init.iter().enumerate() {
+                let idx = dfa.index(state, b);
+                state = match dfa.table[idx] {
+                    Step::Goto(s) => s,
+                    Step::Accept(_) => return Err(LexemError::NonPrefixFree),
+                    Step::Dead => {
+                        let s = dfa.add_state(token[..=i].to_vec());
+                        dfa.table[idx] = Step::Goto(s);
+                        s
+                    }
+                };
+            }
+
+            let idx = dfa.index(state, last);
+            match dfa.table[idx] {
+                Step::Dead => dfa.table[idx] = Step::Accept(l),
+                _ => return Err(LexemError::NonPrefixFree),
+            }
+        }
+
+        Ok(dfa)
+    }
+
+    fn add_state(&mut self, prefix: Vec<u8>) -> StateId {
+        let id = self.prefixes.len() as StateId;
+        self.table
+            .extend(std::iter::repeat_n(Step::Dead, self.n_classes));
+        self.prefixes.push(prefix);
+        id
+    }
+
+    fn index(&self, state: StateId, byte: u8) -> usize {
+        state as usize * self.n_classes + self.classes[byte as usize] as usize
+    }
+
+    #[inline]
+    pub fn step(&self, state: StateId, byte: u8) -> Step<L> {
+        // SAFETY: `state` is always produced by this table and classes are
+        // smaller than `n_classes`
+        unsafe { *self.table.get_unchecked(self.index(state, byte)) }
+    }
+
+    /// Bytes consumed from [`START`] to reach `state`
+    pub fn prefix(&self, state: StateId) -> &[u8] {
+        &self.prefixes[state as usize]
+    }
+
+    pub fn n_states(&self) -> usize {
+        self.prefixes.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build(tokens: &[&str]) -> Result<Dfa<usize>, LexemError> {
+        Dfa::build(tokens.iter().enumerate().map(|(i, t)| (i, t.as_bytes())))
+    }
+
+    #[test]
+    fn test_build_dfa() {
+        let dfa = build(&["aa", "ab", "b"]).unwrap();
+
+        assert_eq!(dfa.n_states(), 2);
+        let Step::Goto(s) = dfa.step(START, b'a') else {
+            panic!("expected to go to a new state")
+        };
+        assert_eq!(dfa.prefix(s), b"a");
+        assert_eq!(dfa.step(s, b'a'), Step::Accept(0));
+        assert_eq!(dfa.step(s, b'b'), Step::Accept(1));
+        assert_eq!(dfa.step(START, b'b'), Step::Accept(2));
+        assert_eq!(dfa.step(START, b'c'), Step::Dead);
+    }
+
+    #[test]
+    fn test_non_prefix_free_dfa() {
+        assert!(matches!(
+            build(&["ab", "a"]),
+            Err(LexemError::NonPrefixFree)
+        ));
+        assert!(matches!(
+            build(&["a", "ab"]),
+            Err(LexemError::NonPrefixFree)
+        ));
+        assert!(matches!(build(&["a", "a"]), Err(LexemError::NonPrefixFree)));
+        assert!(matches!(build(&["a", ""]), Err(LexemError::EmptyLexem)));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+use caseless::Caseless;
+use unicode_normalization::{IsNormalized, UnicodeNormalization};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NormalForm {
+    Nfc,
+    Nfkc,
+}
+
+/// How tokens and input are brought to a common form before matching, so that
+/// for example "HaJiMi", "ｈａｊｉｍｉ" and "hajimi" all match the same token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Normalization {
+    pub form: Option<NormalForm>,
+    pub case_fold: bool,
+}
+
+impl Normalization {
+    pub fn is_none(&self) -> bool {
+        self.form.is_none() && !self.case_fold
+    }
+
+    pub fn normalize(&self, s: &str) -> String {
+        let mut out = VecDeque::new();
+        let mut normalizer = Normalizer::default();
+        s.chars().for_each(|c| normalizer.push(self, c, &mut out));
+        normalizer.finish(self, &mut out);
+        out.into_iter().collect()
+    }
+
+    fn apply(&self, s: &str, out: &mut VecDeque<char>) {
+        let folded = if self.case_fold {
+            s.chars().default_case_fold().collect()
+        } else {
+            s.to_string()
+        };
+        match self.form {
+            Some(NormalForm::Nfc) => out.extend(folded.nfc()),
+            Some(NormalForm::Nfkc) => out.extend(folded.nfkc()),
+            None => out.extend(folded.chars()),
+        }
+    }
+
+    /// Whether input can be cut right before `c` without changing the result,
+    /// that is `c` never combines with or reorders around chars before it
+    fn is_stable(&self, c: char) -> bool {
+        let Some(c) = (if self.case_fold {
+            std::iter::once(c).default_case_fold().next()
+        } else {
+            Some(c)
+        }) else {
+            return true;
+        };
+
+        let quick = match self.form {
+            Some(NormalForm::Nfc) => unicode_normalization::is_nfc_quick(std::iter::once(c)),
+            Some(NormalForm::Nfkc) => unicode_normalization::is_nfkc_quick(std::iter::once(c)),
+            None => return true,
+        };
+        quick == IsNormalized::Yes && unicode_normalization::char::canonical_combining_class(c) == 0
+    }
+}
+
+/// Normalizes chars pushed one by one, holding back those that may still
+/// combine with chars to come.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Normalizer {
+    pending: String,
+}
+
+impl Normalizer {
+    pub fn push(&mut self, normalization: &Normalization, c: char, out: &mut VecDeque<char>) {
+        if normalization.is_stable(c) {
+            normalization.apply(&self.pending, out);
+            self.pending.clear();
+        }
+        self.pending.push(c);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn finish(&mut self, normalization: &Normalization, out: &mut VecDeque<char>) {
+        normalization.apply(&self.pending, out);
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let nfkc_fold = Normalization {
+            form: Some(NormalForm::Nfkc),
+            case_fold: true,
+        };
+        assert_eq!(nfkc_fold.normalize("ＨａJiMi"), "hajimi");
+        assert_eq!(nfkc_fold.normalize("曼波"), "曼波");
+
+        let nfc = Normalization {
+            form: Some(NormalForm::Nfc),
+            case_fold: false,
+        };
+        assert_eq!(nfc.normalize("Cafe\u{301}"), "Caf\u{e9}");
+        assert_eq!(nfc.normalize("\u{1100}\u{1161}"), "\u{ac00}");
+        assert_eq!(
+            Normalization::default().normalize("Cafe\u{301}"),
+            "Cafe\u{301}"
+        );
+    }
+}
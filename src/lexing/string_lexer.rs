@@ -1,67 +1,276 @@
-use std::{
-    collections::{HashMap, HashSet},
-    ops::Deref,
-};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
 
+use super::byte_lexer::{ByteLexer, ReaderIter};
+use super::dfa::{self, Dfa, StateId, Step};
+use super::normalize::{Normalization, Normalizer};
+use super::{Error, LexemError, iter, iter_from_error};
 use crate::letters::{LetterId, LetterIdIndexed};
 
+/// Lexes strings into the [`LetterId`] of tokens.
+///
+/// The token trie is compiled into a [`Dfa`] over UTF-8 bytes, so lexing a char
+/// costs one table lookup for each of its bytes.
 #[derive(Debug, Clone)]
-pub struct Tree(super::Tree<char, LetterId, HashMap<char, Tree>>);
+pub struct StringLexer {
+    dfa: Dfa<LetterId>,
+    /// All chars appearing in some normalized token
+    chars: HashSet<char>,
+    normalization: Normalization,
+}
+
+impl StringLexer {
+    pub fn new(tokens: &LetterIdIndexed<String>) -> Result<Self, LexemError> {
+        Self::with_normalization(tokens, Normalization::default())
+    }
+
+    /// Match input against tokens after bringing both to the form described by
+    /// `normalization`.
+    ///
+    /// Fails with [`LexemError::Collision`] if two tokens become the same.
+    pub fn with_normalization(
+        tokens: &LetterIdIndexed<String>,
+        normalization: Normalization,
+    ) -> Result<Self, LexemError> {
+        Self::with_aliases(
+            &tokens.map_by_ref(|_, token| vec![token.clone()]),
+            normalization,
+        )
+    }
+
+    /// Like [`StringLexer::with_normalization`], but each letter may be spelled
+    /// in several ways, all of which must be prefix-free together.
+    pub fn with_aliases(
+        spellings: &LetterIdIndexed<Vec<String>>,
+        normalization: Normalization,
+    ) -> Result<Self, LexemError> {
+        let mut seen: HashMap<String, (LetterId, &String)> = HashMap::new();
+        let mut normalized = Vec::new();
+        for (letter_id, aliases) in spellings.iter_with_id() {
+            for alias in aliases {
+                let token = normalization.normalize(alias);
+                match seen.get(&token) {
+                    // The same letter spelled twice is harmless
+                    Some((other, _)) if *other == letter_id => continue,
+                    Some((_, other)) => {
+                        return Err(LexemError::Collision(other.to_string(), alias.to_string()));
+                    }
+                    None => {}
+                }
+                seen.insert(token.clone(), (letter_id, alias));
+                normalized.push((letter_id, token));
+            }
+        }
+
+        let chars = normalized
+            .iter()
+            .flat_map(|(_, token)| token.chars())
+            .collect();
+
+        let dfa = Dfa::build(
+            normalized
+                .iter()
+                .map(|(letter_id, token)| (*letter_id, token.as_bytes())),
+        )?;
+
+        Ok(Self {
+            dfa,
+            chars,
+            normalization,
+        })
+    }
+
+    pub fn normalization(&self) -> &Normalization {
+        &self.normalization
+    }
+
+    /// Chars that may appear in honey water, in no particular order
+    pub fn significant_chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars.iter().copied()
+    }
+
+    /// Feed `c` to the automaton at `state`
+    fn step_char(&self, state: StateId, c: char) -> Step<LetterId> {
+        let mut buf = [0; 4];
+        let mut step = Step::Goto(state);
+        for &b in c.encode_utf8(&mut buf).as_bytes() {
+            step = match step {
+                Step::Goto(s) => self.dfa.step(s, b),
+                _ => return Step::Dead,
+            };
+        }
+        step
+    }
+
+    /// Feed normalized `c` to the automaton at `state`, returning the token if
+    /// one is finished by `c`.
+    ///
+    /// A rejected `c` is dropped along with the prefix before it, and lexing starts
+    /// over from the next char, as [`ByteLexer`] does with raw bytes.
+    pub(super) fn feed_char<E>(
+        &self,
+        state: &mut StateId,
+        c: char,
+    ) -> Option<Result<LetterId, Error<char, E>>> {
+        match self.step_char(*state, c) {
+            Step::Goto(s) => {
+                *state = s;
+                None
+            }
+            Step::Accept(l) => {
+                *state = dfa::START;
+                Some(Ok(l))
+            }
+            Step::Dead => {
+                let error = self.rejected(*state, c);
+                *state = dfa::START;
+                Some(Err(error))
+            }
+        }
+    }
+
+    pub(super) fn prefix(&self, state: StateId) -> Vec<char> {
+        // Tokens are valid UTF-8 and chars are fed as a whole, so the prefix of
+        // any state we stop at is valid UTF-8 as well
+        str::from_utf8(self.dfa.prefix(state))
+            .expect("state should be at char boundary")
+            .chars()
+            .collect()
+    }
+
+    fn rejected<E>(&self, state: StateId, c: char) -> Error<char, E> {
+        self.rejected_after(self.prefix(state), c)
+    }
+
+    pub(super) fn rejected_after<E>(&self, prefix: Vec<char>, c: char) -> Error<char, E> {
+        if self.chars.contains(&c) {
+            Error::Unexpected(prefix, c)
+        } else {
+            Error::Invalid(c)
+        }
+    }
+
+    pub(super) fn dfa(&self) -> &Dfa<LetterId> {
+        &self.dfa
+    }
+
+    /// Lex raw UTF-8 bytes fed in arbitrary chunks
+    pub fn byte_lexer(&self) -> ByteLexer<'_> {
+        ByteLexer::new(self)
+    }
 
-impl Deref for Tree {
-    type Target = super::Tree<char, LetterId, HashMap<char, Tree>>;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    /// Lex all bytes from `reader`
+    pub fn lex_reader<R: Read>(&self, reader: R) -> ReaderIter<'_, R> {
+        ReaderIter::new(self.byte_lexer(), reader)
     }
+
+    fn terminated<E>(&self, state: StateId) -> Option<Error<char, E>> {
+        (state != dfa::START).then(|| Error::UnexpectedTermination(self.prefix(state)))
+    }
+}
+
+/// Where lexing a stream of chars is at
+#[derive(Debug, Clone, Default)]
+struct Cursor {
+    state: StateId,
+    normalizer: Normalizer,
+    /// Normalized chars not yet fed to the automaton
+    ready: VecDeque<char>,
+    exhausted: bool,
 }
 
-impl From<super::Tree<char, LetterId, HashMap<char, Tree>>> for Tree {
-    fn from(value: super::Tree<char, LetterId, HashMap<char, Tree>>) -> Self {
-        Self(value)
+impl Cursor {
+    fn next<E>(
+        &mut self,
+        lexer: &StringLexer,
+        mut incoming: impl FnMut() -> Option<Result<char, E>>,
+    ) -> Option<Result<LetterId, Error<char, E>>> {
+        loop {
+            while let Some(c) = self.ready.pop_front() {
+                if let Some(r) = lexer.feed_char(&mut self.state, c) {
+                    return Some(r);
+                }
+            }
+            if self.exhausted {
+                return lexer.terminated(self.state).map(Err);
+            }
+
+            match incoming() {
+                Some(Ok(c)) if lexer.normalization.is_none() => {
+                    if let Some(r) = lexer.feed_char(&mut self.state, c) {
+                        return Some(r);
+                    }
+                }
+                Some(Ok(c)) => self
+                    .normalizer
+                    .push(&lexer.normalization, c, &mut self.ready),
+                Some(Err(e)) => return Some(Err(Error::Parent(e))),
+                None => {
+                    self.normalizer
+                        .finish(&lexer.normalization, &mut self.ready);
+                    self.exhausted = true;
+                }
+            }
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct StringLexer {
-    tree: HashMap<char, Tree>,
+pub struct Iter<'t, It> {
+    lexer: &'t StringLexer,
+    cursor: Cursor,
+    incoming: It,
+}
+
+impl<'t, It> Iterator for Iter<'t, It>
+where
+    It: Iterator<Item = char>,
+{
+    type Item = iter::Result<LetterId, char>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor
+            .next(self.lexer, || self.incoming.next().map(Ok::<_, !>))
+    }
+}
+
+pub struct IterFromError<'t, It, E> {
+    lexer: &'t StringLexer,
+    cursor: Cursor,
+    incoming: It,
+    _phantom: std::marker::PhantomData<E>,
 }
 
-pub type Iter<'t, It> = super::iter::LexingIter<'t, char, LetterId, HashMap<char, Tree>, It>;
-pub type IterFromError<'t, It, E> =
-    super::iter_from_error::LexingIter<'t, char, LetterId, HashMap<char, Tree>, It, E>;
+impl<'t, It, E> Iterator for IterFromError<'t, It, E>
+where
+    It: Iterator<Item = Result<char, E>>,
+{
+    type Item = iter_from_error::Result<LetterId, char, E>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next(self.lexer, || self.incoming.next())
+    }
+}
 
 impl super::Lexer for StringLexer {
     type Src = char;
     type Dst = LetterId;
 
     fn lex<It: Iterator<Item = Self::Src>>(&self, incoming: It) -> Iter<'_, It> {
-        super::iter::LexingIter::new(&self.tree, incoming)
+        Iter {
+            lexer: self,
+            cursor: Cursor::default(),
+            incoming,
+        }
     }
 
     fn lex_from_error<E, It: Iterator<Item = Result<Self::Src, E>>>(
         &self,
         incoming: It,
     ) -> IterFromError<'_, It, E> {
-        super::iter_from_error::LexingIter::new(&self.tree, incoming)
-    }
-}
-
-impl StringLexer {
-    pub fn new(tokens: &LetterIdIndexed<String>) -> Result<Self, super::LexemError> {
-        let chars = tokens
-            .iter()
-            .map(|s| s.chars())
-            .flatten()
-            .collect::<HashSet<_>>();
-
-        let roots = super::build_tree::<HashMap<_, _>, _, _, _, _>(
-            tokens
-                .iter_with_id()
-                .map(|(letter_id, token)| (letter_id, super::Code::new(token.chars()))),
-            chars.iter().cloned(),
-        )?;
-
-        Ok(Self { tree: roots })
+        IterFromError {
+            lexer: self,
+            cursor: Cursor::default(),
+            incoming,
+            _phantom: std::marker::PhantomData,
+        }
     }
 }
 
@@ -151,3 +360,76 @@ mod test {
             .collect::<Vec<_>>();
     }
 }
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use std::collections::HashMap;
+    use std::ops::Deref;
+
+    use super::super::{Code, Lexer, Tree, build_tree, iter::LexingIter};
+    use super::*;
+    use crate::hajimi::hajimi_tokens;
+
+    /// The recursive trie `StringLexer` used to walk, kept as a baseline
+    #[derive(Debug, Clone)]
+    struct TrieNode(Tree<char, LetterId, HashMap<char, TrieNode>>);
+
+    impl Deref for TrieNode {
+        type Target = Tree<char, LetterId, HashMap<char, TrieNode>>;
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+
+    impl From<Tree<char, LetterId, HashMap<char, TrieNode>>> for TrieNode {
+        fn from(value: Tree<char, LetterId, HashMap<char, TrieNode>>) -> Self {
+            Self(value)
+        }
+    }
+
+    fn honey_water() -> String {
+        let tokens = hajimi_tokens();
+        (0..100_000usize)
+            .map(|i| tokens.iter().nth(i * 7 % tokens.len()).unwrap().as_str())
+            .collect()
+    }
+
+    #[bench]
+    fn bench_dfa_lexer(b: &mut test::Bencher) {
+        let lexer = StringLexer::new(&hajimi_tokens()).unwrap();
+        let s = honey_water();
+        b.bytes = s.len() as u64;
+
+        b.iter(|| {
+            lexer.lex(s.chars()).for_each(|x| {
+                test::black_box(x.unwrap());
+            })
+        });
+    }
+
+    #[bench]
+    fn bench_trie_lexer(b: &mut test::Bencher) {
+        let tokens = hajimi_tokens();
+        let chars = tokens
+            .iter()
+            .flat_map(|s| s.chars())
+            .collect::<HashSet<_>>();
+        let roots: HashMap<char, TrieNode> = build_tree::<HashMap<_, _>, _, _, _, _>(
+            tokens
+                .iter_with_id()
+                .map(|(letter_id, token)| (letter_id, Code::new(token.chars()))),
+            chars.iter().cloned(),
+        )
+        .unwrap();
+        let s = honey_water();
+        b.bytes = s.len() as u64;
+
+        b.iter(|| {
+            LexingIter::<_, LetterId, _, _>::new(&roots, s.chars()).for_each(|x| {
+                test::black_box(x.unwrap());
+            })
+        });
+    }
+}
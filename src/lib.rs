@@ -1,24 +1,45 @@
 #![feature(never_type)]
 #![feature(iter_array_chunks)]
-#![feature(string_into_chars)]
 #![allow(refining_impl_trait)]
+#![cfg_attr(test, feature(test))]
 
 mod bits_key;
 mod characters;
 pub mod cli;
+mod compression;
+mod container;
+mod crypto;
+mod detect;
+mod dyn_jimi;
 mod encoding;
+mod fec;
 mod hajimi;
 mod jimi;
+mod key;
 mod letters;
 mod lexing;
+mod token_set;
+mod transcode;
 
 pub use bits_key::{Bits, BitsIter, bits};
 
-pub use characters::{CharacterCounter, CharacterFrequency};
+pub use characters::{CharacterCounter, CharacterFrequency, Smoothing};
+pub use compression::{Compression, DecompressError};
+pub use container::{Codebook, ContainerError, Flag, Header, RawHeader};
+pub use crypto::{CryptoError, Encryption, Kdf};
+pub use detect::{Guess, rank, uniform_candidates};
+pub use dyn_jimi::{DynJimiDecoder, DynJimiEncoder, DynJimiEncoding, UnsupportedWidth, WIDTHS};
 pub use encoding::{Decoder, Encoder, Encoding};
+pub use fec::{Fec, FecDecoder, FecError, FecStats, Redundancy};
 pub use hajimi::{HAJIMI, hajimi_tokens};
-pub use jimi::{JimiDecoder, JimiEncoder, JimiEncoding, JimiError};
+pub use jimi::{
+    Checksum, JimiDecodeState, JimiDecoder, JimiEncoder, JimiEncoding, JimiError, trailer_len,
+};
+pub use key::Key;
 pub use letters::LetterCosts;
-pub use lexing::{LexemError, Lexer, StringLexer};
+pub use lexing::normalize::{NormalForm, Normalization};
+pub use lexing::{ByteLexer, LexemError, Lexer, StringLexer};
+pub use token_set::{TOKEN_SETS, TokenSet};
+pub use transcode::{EncodeWriter, transcode};
 
 pub use serde_json;
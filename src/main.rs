@@ -1,9 +1,14 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 use hajiman::cli::{Cli, run};
 
-fn main() {
+fn main() -> ExitCode {
     match run(Cli::parse()) {
-        Ok(()) => {}
-        Err(e) => println!("{e}"),
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::from(e.exit_code())
+        }
     }
 }
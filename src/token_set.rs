@@ -0,0 +1,133 @@
+use crate::hajimi::HAJIMI;
+use crate::letters::LetterIdIndexed;
+
+/// A vocabulary shipped with the crate, which encodings may refer to by name
+/// instead of listing every token.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TokenSet {
+    name: &'static str,
+    description: &'static str,
+    /// Spellings of each letter separated by spaces, the first of which is emitted
+    /// when encoding
+    letters: &'static [&'static str],
+}
+
+pub const TOKEN_SETS: &[TokenSet] = &[
+    TokenSet {
+        name: "hajimi",
+        description: "The original honey water vocabulary",
+        letters: &HAJIMI,
+    },
+    TokenSet {
+        name: "hajimi-pinyin",
+        description: "Hajimi phrases spelled in pinyin, for keyboards without Chinese input",
+        letters: &[
+            "hajimi",
+            "nabeiluduo",
+            "axiga",
+            "haiyaku",
+            "oumaziri",
+            "manbo",
+            "dagoujiao",
+            "dingdongji",
+            "wuo",
+            "waqia",
+        ],
+    },
+    TokenSet {
+        name: "meme",
+        description: "Other internet meme phrases",
+        letters: &[
+            "芜湖",
+            "起飞",
+            "绝绝子",
+            "破防了",
+            "蚌埠住了",
+            "栓Q",
+            "泰裤辣",
+            "遥遥领先",
+        ],
+    },
+    TokenSet {
+        name: "emoji",
+        description: "Cats, honey and water, one emoji per letter",
+        letters: &["🐱", "🍯", "💧", "🐾", "🎵", "🌟", "🐟", "🥛"],
+    },
+    TokenSet {
+        name: "morse",
+        description: "Dots and dashes",
+        letters: &["· .", "— -"],
+    },
+    TokenSet {
+        name: "binary",
+        description: "Nothing but 哈 and 基",
+        letters: &["哈", "基"],
+    },
+];
+
+impl TokenSet {
+    pub fn by_name(name: &str) -> Option<&'static TokenSet> {
+        TOKEN_SETS.iter().find(|set| set.name == name)
+    }
+
+    pub fn all() -> &'static [TokenSet] {
+        TOKEN_SETS
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// Spellings of each letter, the first of which is emitted when encoding
+    pub fn tokens(&self) -> LetterIdIndexed<Vec<String>> {
+        LetterIdIndexed::new(
+            self.letters
+                .iter()
+                .map(|spellings| spellings.split(' ').map(String::from).collect())
+                .collect(),
+        )
+    }
+
+    /// The spelling emitted for each letter when encoding
+    pub fn canonical_tokens(&self) -> LetterIdIndexed<String> {
+        self.tokens()
+            .map(|_, mut spellings| spellings.swap_remove(0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CharacterFrequency, JimiEncoding, bits::Bits8};
+
+    #[test]
+    fn test_token_sets() {
+        for (i, set) in TOKEN_SETS.iter().enumerate() {
+            assert!(
+                TOKEN_SETS[..i].iter().all(|other| other.name != set.name),
+                "duplicated token set {}",
+                set.name
+            );
+
+            let encoding =
+                JimiEncoding::<Bits8>::from_token_set(set, &CharacterFrequency::all_equal());
+            let (encoder, decoder) = (encoding.encoder(), encoding.decoder().unwrap());
+
+            let src: Vec<u8> = (0..=255).collect();
+            let encoded: String = encoder.encode(&src).data.collect();
+            let mut decoded = Vec::new();
+            decoder.decode(&encoded, &mut decoded).unwrap();
+            assert_eq!(decoded, src, "token set {}", set.name);
+        }
+
+        assert_eq!(
+            TokenSet::by_name("hajimi").unwrap().canonical_tokens(),
+            crate::hajimi_tokens()
+        );
+        assert!(TokenSet::by_name("nope").is_none());
+    }
+}
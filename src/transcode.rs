@@ -0,0 +1,208 @@
+use std::io::{Read, Write};
+
+use crate::bits_key::{Bits, ConcatError};
+use crate::jimi::{Checksum, JimiDecoder, JimiEncoder, JimiError};
+
+/// Writer encoding the bytes written to it into honey water, a group of symbols at a
+/// time as soon as it is complete.
+///
+/// [`EncodeWriter::finish`] encodes what is left, padding the last group, and the
+/// checksum trailer if asked for.
+pub struct EncodeWriter<'a, B, W>
+where
+    B: Bits,
+    W: Write,
+{
+    encoder: &'a JimiEncoder<B>,
+    inner: W,
+    /// Bytes not yet making up a whole group of symbols
+    pending: Vec<u8>,
+    checksum: Option<Checksum<B>>,
+}
+
+impl<'a, B, W> EncodeWriter<'a, B, W>
+where
+    B: Bits,
+    W: Write,
+{
+    /// Writer into `inner`, followed by a checksum trailer if `checksum` is set
+    pub fn new(encoder: &'a JimiEncoder<B>, inner: W, checksum: bool) -> Self {
+        Self {
+            encoder,
+            inner,
+            pending: Vec::new(),
+            checksum: checksum.then(Checksum::new),
+        }
+    }
+
+    fn write_encoded(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(bytes);
+        }
+        for s in self.encoder.encode(bytes).data {
+            self.inner.write_all(s.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Encode what is left and the checksum trailer, returning the inner writer
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+        self.write_encoded(&pending)?;
+        if let Some(checksum) = self.checksum.take() {
+            for s in self.encoder.encode_checksum(checksum.finish()) {
+                self.inner.write_all(s.as_bytes())?;
+            }
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<B, W> Write for EncodeWriter<'_, B, W>
+where
+    B: Bits,
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let group_bytes = B::group_bytes();
+        self.pending.extend_from_slice(buf);
+        let whole = self.pending.len() - self.pending.len() % group_bytes;
+        if whole > 0 {
+            let rest = self.pending.split_off(whole);
+            let groups = std::mem::replace(&mut self.pending, rest);
+            self.write_encoded(&groups)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer dropping everything after the first `remaining` bytes, which can only be
+/// padding or garbage, and counting all of them in `written`
+pub(crate) struct Truncated<W> {
+    pub(crate) inner: W,
+    pub(crate) remaining: u64,
+    pub(crate) written: u64,
+}
+
+impl<W> Truncated<W> {
+    /// Writer passing on the first `length` bytes to `inner`, or all if not given
+    pub(crate) fn new(inner: W, length: Option<u64>) -> Self {
+        Self {
+            inner,
+            remaining: length.unwrap_or(u64::MAX),
+            written: 0,
+        }
+    }
+}
+
+impl<W> Write for Truncated<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        self.inner.write_all(&buf[..n])?;
+        self.remaining -= n as u64;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decode honey water from `reader` and encode the bytes again into `writer` as they
+/// come, so that the payload is never held whole. Returns how many bytes were decoded.
+///
+/// The input ends with a checksum trailer if `checked` is set. Only the first `length`
+/// bytes are passed on if it is given, so that padding to whole groups of the source
+/// width does not become data in the target, whose width can differ.
+pub fn transcode<S, T, W>(
+    reader: impl Read,
+    decoder: &JimiDecoder<S>,
+    checked: bool,
+    writer: EncodeWriter<'_, T, W>,
+    length: Option<u64>,
+) -> Result<(u64, W), ConcatError<JimiError>>
+where
+    S: Bits,
+    T: Bits,
+    W: Write,
+{
+    let mut truncated = Truncated::new(writer, length);
+    match checked {
+        true => decoder.decode_reader_checked(reader, &mut truncated),
+        false => decoder.decode_reader(reader, &mut truncated),
+    }?;
+    let inner = truncated.inner.finish().map_err(ConcatError::Io)?;
+    Ok((truncated.written, inner))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::bits::{Bits4, Bits6, Bits8};
+    use crate::{CharacterFrequency, JimiEncoding, hajimi_tokens};
+
+    fn encoding<B: Bits>() -> JimiEncoding<B> {
+        JimiEncoding::new(hajimi_tokens(), &CharacterFrequency::all_equal())
+    }
+
+    fn check<S: Bits, T: Bits>(data: &[u8], checked: bool) {
+        let (source, target) = (encoding::<S>(), encoding::<T>());
+        let (source_encoder, mut honey_water) = (source.encoder(), Vec::new());
+        let mut writer = EncodeWriter::new(&source_encoder, &mut honey_water, checked);
+        // Written in pieces cutting groups of symbols apart
+        for piece in data.chunks(5) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let target_encoder = target.encoder();
+        let (n, transcoded) = transcode(
+            Cursor::new(&honey_water),
+            &source.decoder().unwrap(),
+            checked,
+            EncodeWriter::new(&target_encoder, Vec::new(), checked),
+            Some(data.len() as u64),
+        )
+        .unwrap();
+        assert!(n >= data.len() as u64);
+
+        let mut decoded = Vec::new();
+        let decoder = target.decoder().unwrap();
+        match checked {
+            true => decoder.decode_reader_checked(Cursor::new(&transcoded), &mut decoded),
+            false => decoder.decode_reader(Cursor::new(&transcoded), &mut decoded),
+        }
+        .unwrap();
+        assert!(decoded.starts_with(data), "{} to {}", S::N, T::N);
+        let group_bytes = T::group_bytes();
+        assert_eq!(
+            decoded.len(),
+            data.len().div_ceil(group_bytes) * group_bytes
+        );
+    }
+
+    #[test]
+    fn test_transcode() {
+        let data = "哈基米 honey water".as_bytes();
+        for checked in [false, true] {
+            check::<Bits6, Bits8>(data, checked);
+            check::<Bits8, Bits6>(data, checked);
+            check::<Bits4, Bits6>(data, checked);
+            check::<Bits6, Bits6>(&data[..4], checked);
+            check::<Bits8, Bits4>(b"", checked);
+        }
+    }
+}